@@ -0,0 +1,320 @@
+//! Constant expressions in assembly operands and directives: numbers, `'c'`
+//! character literals, labels and constants, `$` for the current address, and
+//! the usual integer operators.
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(i64),
+    /// A label or constant, with local labels already qualified.
+    Ident(String),
+    /// `$`, the address of the current instruction.
+    Here,
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(i64),
+    Ident(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Num(n) => write!(f, "{n}"),
+            Tok::Ident(name) => write!(f, "{name}"),
+            Tok::Punct(p) => write!(f, "{p}"),
+        }
+    }
+}
+
+const PUNCTS: &[&str] = &[
+    "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "(", ")", "$",
+];
+
+/// Binary operators from loosest to tightest binding.
+const LEVELS: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(src: &str) -> Result<Vec<Tok>, String> {
+    let mut toks = Vec::new();
+    let mut rest = src.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+            toks.push(Tok::Num(parse_int(&rest[..end])?));
+            rest = &rest[end..];
+        } else if c == '\'' {
+            let (ch, len) = parse_char(rest)?;
+            toks.push(Tok::Num(ch as i64));
+            rest = &rest[len..];
+        } else if is_ident_start(c) {
+            let end = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+            toks.push(Tok::Ident(rest[..end].to_owned()));
+            rest = &rest[end..];
+        } else if let Some(&p) = PUNCTS.iter().find(|&&p| rest.starts_with(p)) {
+            toks.push(Tok::Punct(p));
+            rest = &rest[p.len()..];
+        } else {
+            return Err(format!("Unexpected character `{c}`"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(toks)
+}
+
+fn parse_int(s: &str) -> Result<i64, String> {
+    let digits = s.replace('_', "");
+    let parsed = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else if let Some(oct) = digits.strip_prefix("0o") {
+        i64::from_str_radix(oct, 8)
+    } else {
+        digits.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number: `{s}`"))
+}
+
+/// Parses a `'c'` literal at the start of `s`, returning the byte and how
+/// much of `s` it took up.
+fn parse_char(s: &str) -> Result<(u8, usize), String> {
+    let body = &s[1..];
+    let (value, len) = match body.as_bytes() {
+        [b'\\', esc, ..] => (unescape_byte(*esc)?, 2),
+        [b, ..] if b.is_ascii() => (*b, 1),
+        _ => return Err("Invalid character literal".to_string()),
+    };
+    if body.as_bytes().get(len) != Some(&b'\'') {
+        return Err("Unterminated character literal".to_string());
+    }
+    Ok((value, len + 2))
+}
+
+/// The byte for the escape `\c`.
+pub fn unescape_byte(c: u8) -> Result<u8, String> {
+    Ok(match c {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'0' => 0,
+        b'\\' => b'\\',
+        b'\'' => b'\'',
+        b'"' => b'"',
+        _ => return Err(format!("Unknown escape `\\{}`", c as char)),
+    })
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+    /// The global label that local labels (`.name`) belong to.
+    scope: String,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for &op in LEVELS[level] {
+                if self.eat(op) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        for op in ['-', '~', '!'] {
+            if self.eat(&op.to_string()) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let tok = self.peek().cloned();
+        self.pos += 1;
+        match tok {
+            Some(Tok::Num(n)) => Ok(Expr::Num(n)),
+            Some(Tok::Ident(name)) => Ok(Expr::Ident(qualify(&self.scope, &name))),
+            Some(Tok::Punct("$")) => Ok(Expr::Here),
+            Some(Tok::Punct("(")) => {
+                let e = self.binary(0)?;
+                if !self.eat(")") {
+                    return Err("Expected `)`".to_string());
+                }
+                Ok(e)
+            }
+            Some(tok) => Err(format!("Unexpected `{tok}`")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Turns a local label `.name` into `scope.name`.
+pub fn qualify(scope: &str, name: &str) -> String {
+    match name.starts_with('.') {
+        true => format!("{scope}{name}"),
+        false => name.to_owned(),
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str, scope: &str) -> Result<Self, String> {
+        let mut p = Parser {
+            toks: tokenize(src)?,
+            pos: 0,
+            scope: scope.to_owned(),
+        };
+        if p.toks.is_empty() {
+            return Err("Expected an expression".to_string());
+        }
+        let e = p.binary(0)?;
+        match p.peek() {
+            None => Ok(e),
+            Some(tok) => Err(format!("Unexpected `{tok}`")),
+        }
+    }
+
+    /// `lookup` gives the value of a label or constant.
+    pub fn eval(
+        &self,
+        here: i64,
+        lookup: &dyn Fn(&str) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Ident(name) => lookup(name)?,
+            Expr::Here => here,
+            Expr::Unary(op, e) => {
+                let v = e.eval(here, lookup)?;
+                match op {
+                    '-' => v.wrapping_neg(),
+                    _ => !v,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let l = lhs.eval(here, lookup)?;
+                let r = rhs.eval(here, lookup)?;
+                match *op {
+                    "|" => l | r,
+                    "^" => l ^ r,
+                    "&" => l & r,
+                    "<<" => l.wrapping_shl(r as u32),
+                    ">>" => l.wrapping_shr(r as u32),
+                    "+" => l.wrapping_add(r),
+                    "-" => l.wrapping_sub(r),
+                    "*" => l.wrapping_mul(r),
+                    "/" | "%" if r == 0 => return Err("Division by zero".to_string()),
+                    "/" => l.wrapping_div(r),
+                    _ => l.wrapping_rem(r),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates `src` at address 0x100, with labels `main` and `main.loop`.
+    fn eval(src: &str) -> Result<i64, String> {
+        let lookup = |name: &str| match name {
+            "main" => Ok(0x80),
+            "main.loop" => Ok(0x84),
+            _ => Err(format!("Unknown `{name}`")),
+        };
+        Expr::parse(src, "main")?.eval(0x100, &lookup)
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("42"), Ok(42));
+        assert_eq!(eval("0x2A"), Ok(42));
+        assert_eq!(eval("0b10_1010"), Ok(42));
+        assert_eq!(eval("0o52"), Ok(42));
+        assert_eq!(eval("'*'"), Ok(42));
+        assert_eq!(eval(r"'\n'"), Ok(10));
+        assert_eq!(eval(r"'\''"), Ok(39));
+        assert_eq!(eval("0x"), Err("Invalid number: `0x`".to_string()));
+        assert_eq!(eval(r"'\q'"), Err(r"Unknown escape `\q`".to_string()));
+        assert_eq!(
+            eval("'ab'"),
+            Err("Unterminated character literal".to_string())
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("1 | 2 ^ 3 & 6"), Ok(1));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("-1 & 0xFF"), Ok(0xFF));
+        assert_eq!(eval("~0 >> 60"), Ok(-1));
+        assert_eq!(eval("7 % 4 * 2"), Ok(6));
+    }
+
+    #[test]
+    fn symbols() {
+        assert_eq!(eval("$"), Ok(0x100));
+        assert_eq!(eval("$ + 2"), Ok(0x102));
+        assert_eq!(eval("main"), Ok(0x80));
+        // A local label belongs to the scope it's used in.
+        assert_eq!(eval(".loop - main"), Ok(4));
+        assert_eq!(eval("main.loop"), Ok(0x84));
+        assert_eq!(eval(".end"), Err("Unknown `main.end`".to_string()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval(""), Err("Expected an expression".to_string()));
+        assert_eq!(eval("1 +"), Err("Unexpected end of expression".to_string()));
+        assert_eq!(eval("1 2"), Err("Unexpected `2`".to_string()));
+        assert_eq!(eval("(1"), Err("Expected `)`".to_string()));
+        assert_eq!(eval("1 # 2"), Err("Unexpected character `#`".to_string()));
+        assert_eq!(eval("1 / 0"), Err("Division by zero".to_string()));
+    }
+}
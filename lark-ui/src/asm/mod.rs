@@ -0,0 +1,917 @@
+//! A built-in assembler for `.lark` sources, so `load prog.lark` doesn't need
+//! `customasm`. It knows the rules in `lark.customasm` (see `isa::INSTRS`)
+//! and the parts of customasm's syntax Lark programs use:
+//!
+//! - `label:` and local `.label:` definitions, `NAME = expr` constants
+//! - `#include "file"` and `#once`. Included `.customasm` files are read for
+//!   their constants (`IO_ADDR` and so on); their `#ruledef`s are skipped,
+//!   since the instruction set is built in.
+//! - `#d8`, `#d16`, `#d32` and `#d` data (strings are allowed), `#res`,
+//!   `#align` (in bits, as in customasm) and `#addr`
+//! - `#bankdef`/`#bank`, which are accepted and ignored: code always goes in
+//!   the `rom` bank at `Memory::ROM_START`, and the output starts there too.
+//!
+//! Assembly takes two passes: the first finds every label's address (all
+//! instruction sizes are fixed), the second encodes.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use lark_vm::cpu::Memory;
+
+use crate::{
+    diagnostic::Diagnostic,
+    isa::{self, Form},
+};
+
+use self::expr::{qualify, unescape_byte, Expr};
+
+mod expr;
+
+/// How deeply `#include`s may nest, and constants may refer to constants.
+const MAX_DEPTH: usize = 32;
+
+/// The output of a successful assembly.
+pub struct Assembled {
+    /// The `rom` bank, starting at `Memory::ROM_START`.
+    pub rom: Vec<u8>,
+    /// Every label and its address. Local labels are named `global.local`.
+    pub labels: BTreeMap<String, u16>,
+    /// The address of each instruction and the file and line it's on.
+    pub lines: Vec<(u16, PathBuf, usize)>,
+}
+
+/// Assembles the file at `path`.
+pub fn assemble_file(path: &Path) -> Result<Assembled, Vec<Diagnostic>> {
+    let mut asm = Assembler::default();
+    asm.read_file(path, None, 0);
+    asm.finish()
+}
+
+/// A line of source, after `#include`s have been expanded.
+struct SrcLine {
+    path: Rc<PathBuf>,
+    line: usize,
+    /// The line as written, for diagnostics.
+    raw: String,
+    /// The code on the line, without the comment or surrounding whitespace.
+    text: String,
+}
+
+enum Operand {
+    Reg(u8),
+    Expr(Expr),
+    /// `offset($base)`
+    Mem {
+        offset: Expr,
+        base: u8,
+    },
+}
+
+enum DataItem {
+    Bytes(Vec<u8>),
+    Expr(Expr),
+}
+
+enum StmtKind {
+    Label(String),
+    Const(String, Expr),
+    Instr {
+        mnemonic: String,
+        opcode: u8,
+        form: Form,
+        operands: Vec<Operand>,
+    },
+    /// `#dN`, with `N / 8` bytes per expression.
+    Data {
+        width: u16,
+        items: Vec<DataItem>,
+    },
+    Res(Expr),
+    Align(Expr),
+    Addr(Expr),
+}
+
+struct Stmt {
+    /// Index into `Assembler::lines`.
+    line: usize,
+    kind: StmtKind,
+}
+
+#[derive(Default)]
+struct Assembler {
+    lines: Vec<SrcLine>,
+    /// Files with `#once` that have already been read.
+    once: HashSet<PathBuf>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Assembler {
+    /// A diagnostic for `self.lines[idx]`. The span is the first thing in
+    /// backticks in `msg` if it's on the line, or else the whole line. Lines
+    /// of `msg` after the first become notes.
+    fn diagnostic(&self, idx: usize, msg: &str) -> Diagnostic {
+        let src = &self.lines[idx];
+        let mut msg_lines = msg.lines();
+        let mut diag =
+            Diagnostic::new(src.path.as_path(), src.line, msg_lines.next().unwrap_or(""))
+                .with_source(src.raw.as_str());
+        if let Some(quoted) = msg.split('`').nth(1) {
+            diag = diag.with_span_of(quoted);
+        }
+        msg_lines.fold(diag, Diagnostic::with_note)
+    }
+
+    fn error(&mut self, idx: usize, msg: impl AsRef<str>) {
+        let diag = self.diagnostic(idx, msg.as_ref());
+        self.errors.push(diag);
+    }
+
+    /// Appends the lines of `path` to `self.lines`, expanding `#include`s.
+    /// `from` is where it was included from, for error messages.
+    fn read_file(&mut self, path: &Path, from: Option<Diagnostic>, depth: usize) {
+        let src = match std::fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(e) => {
+                let msg = format!("Error reading `{}`: {e}", path.display());
+                self.errors.push(match from {
+                    Some(include) => Diagnostic { msg, ..include },
+                    None => Diagnostic::new(path, 0, msg),
+                });
+                return;
+            }
+        };
+        self.read_src(path, &src, depth);
+    }
+
+    /// Appends `src`, the contents of `path`, to `self.lines`.
+    fn read_src(&mut self, path: &Path, src: &str, depth: usize) {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.once.contains(&canonical) {
+            return;
+        }
+
+        let rc_path = Rc::new(path.to_path_buf());
+        for (i, text) in src.lines().enumerate() {
+            let line = i + 1;
+            let code = strip_comment(text).trim();
+            if code == "#once" {
+                self.once.insert(canonical.clone());
+                continue;
+            }
+            if let Some(arg) = code.strip_prefix("#include") {
+                let here = Diagnostic::new(path, line, "").with_source(text);
+                let Some(file) = unquote(arg.trim()) else {
+                    self.errors.push(Diagnostic {
+                        msg: "Expected `#include \"<FILE>\"`".to_string(),
+                        ..here
+                    });
+                    continue;
+                };
+                let file = String::from_utf8_lossy(&file).into_owned();
+                let here = here.with_span_of(&file);
+                if depth >= MAX_DEPTH {
+                    self.errors.push(Diagnostic {
+                        msg: "`#include`s are nested too deeply".to_string(),
+                        ..here
+                    });
+                    continue;
+                }
+                let inc = path.parent().unwrap_or(Path::new("")).join(file);
+                self.read_file(&inc, Some(here), depth + 1);
+                continue;
+            }
+            self.lines.push(SrcLine {
+                path: rc_path.clone(),
+                line,
+                raw: text.to_owned(),
+                text: code.to_owned(),
+            });
+        }
+    }
+
+    fn parse(&mut self) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        let mut scope = String::new();
+        // Brace depth of a `#bankdef`/`#ruledef` block being skipped, and
+        // whether its `{` has been seen yet.
+        let mut block = None::<(usize, bool)>;
+
+        for idx in 0..self.lines.len() {
+            let mut text = self.lines[idx].text.clone();
+
+            if block.is_none() && is_block_directive(&text) {
+                block = Some((0, false));
+            }
+            if let Some((depth, opened)) = &mut block {
+                *opened |= text.contains('{');
+                *depth += text.matches('{').count();
+                *depth -= text.matches('}').count().min(*depth);
+                if *opened && *depth == 0 {
+                    block = None;
+                }
+                continue;
+            }
+
+            // Labels, possibly followed by more on the same line.
+            while let Some((name, rest)) = split_label(&text) {
+                let qualified = qualify(&scope, name);
+                if !name.starts_with('.') {
+                    scope = qualified.clone();
+                }
+                stmts.push(Stmt {
+                    line: idx,
+                    kind: StmtKind::Label(qualified),
+                });
+                text = rest.trim().to_owned();
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+            match parse_stmt(&text, &scope) {
+                Ok(Some(kind)) => stmts.push(Stmt { line: idx, kind }),
+                Ok(None) => {}
+                Err(e) => self.error(idx, e),
+            }
+        }
+
+        stmts
+    }
+
+    fn finish(mut self) -> Result<Assembled, Vec<Diagnostic>> {
+        let stmts = self.parse();
+
+        // First pass: addresses.
+        let mut syms = Symbols::default();
+        // Where each label and constant was defined, as an index into `self.lines`.
+        let mut defined = HashMap::<String, usize>::new();
+        let mut addrs = Vec::with_capacity(stmts.len());
+        let mut addr = Memory::ROM_START as i64;
+        for stmt in &stmts {
+            addrs.push(addr);
+            let size = match &stmt.kind {
+                StmtKind::Label(name) | StmtKind::Const(name, _) => {
+                    if let Some(&first) = defined.get(name) {
+                        let first = &self.lines[first];
+                        let note =
+                            format!("first defined at {}:{}", first.path.display(), first.line);
+                        // As written: a local label without its scope.
+                        let name = match name.rsplit_once('.') {
+                            Some((_, local)) => format!(".{local}"),
+                            None => name.clone(),
+                        };
+                        self.error(stmt.line, format!("`{name}` is defined twice\n{note}"));
+                    } else {
+                        defined.insert(name.clone(), stmt.line);
+                        match &stmt.kind {
+                            StmtKind::Const(_, e) => {
+                                syms.consts.insert(name.clone(), (e.clone(), addr));
+                            }
+                            _ => {
+                                syms.labels.insert(name.clone(), addr);
+                            }
+                        }
+                    }
+                    0
+                }
+                StmtKind::Instr { form, .. } => form.size() as i64,
+                StmtKind::Data { width, items } => items
+                    .iter()
+                    .map(|item| match item {
+                        DataItem::Bytes(b) => b.len() as i64,
+                        DataItem::Expr(_) => *width as i64,
+                    })
+                    .sum(),
+                StmtKind::Res(e) => match syms.eval(e, addr) {
+                    Ok(n) if n >= 0 => n,
+                    Ok(n) => {
+                        self.error(stmt.line, format!("Can't reserve {n} bytes"));
+                        0
+                    }
+                    Err(e) => {
+                        self.error(stmt.line, e);
+                        0
+                    }
+                },
+                StmtKind::Align(e) => match syms.eval(e, addr) {
+                    Ok(bits) if bits > 0 && bits % 8 == 0 => {
+                        let align = bits / 8;
+                        (align - addr.rem_euclid(align)) % align
+                    }
+                    Ok(bits) => {
+                        self.error(stmt.line, format!("Can't align to {bits} bits"));
+                        0
+                    }
+                    Err(e) => {
+                        self.error(stmt.line, e);
+                        0
+                    }
+                },
+                StmtKind::Addr(e) => {
+                    match syms.eval(e, addr) {
+                        Ok(new) => addr = new,
+                        Err(e) => self.error(stmt.line, e),
+                    }
+                    0
+                }
+            };
+            addr += size;
+        }
+
+        // Second pass: encoding.
+        let mut rom = Vec::new();
+        let mut lines = Vec::new();
+        for (stmt, &addr) in stmts.iter().zip(&addrs) {
+            let bytes = match encode(&stmt.kind, addr, &syms) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    self.error(stmt.line, e);
+                    continue;
+                }
+            };
+            if bytes.is_empty() {
+                continue;
+            }
+            let offset = addr - Memory::ROM_START as i64;
+            if offset < 0 || addr + bytes.len() as i64 > 0x1_0000 {
+                self.error(
+                    stmt.line,
+                    format!("Address 0x{addr:04X} is outside the `rom` bank"),
+                );
+                continue;
+            }
+            let end = offset as usize + bytes.len();
+            if rom.len() < end {
+                rom.resize(end, 0);
+            }
+            rom[offset as usize..end].copy_from_slice(&bytes);
+            if let StmtKind::Instr { .. } = stmt.kind {
+                let src = &self.lines[stmt.line];
+                lines.push((addr as u16, src.path.to_path_buf(), src.line));
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Assembled {
+            rom,
+            labels: syms
+                .labels
+                .into_iter()
+                .map(|(name, addr)| (name, addr as u16))
+                .collect(),
+            lines,
+        })
+    }
+}
+
+fn parse_stmt(text: &str, scope: &str) -> Result<Option<StmtKind>, String> {
+    if let Some(directive) = text.strip_prefix('#') {
+        let (name, args) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((directive, ""));
+        let args = args.trim();
+        let expr = || Expr::parse(args, scope);
+        return Ok(Some(match name {
+            "bank" | "bits" => return Ok(None),
+            "d" | "d8" | "d16" | "d32" => {
+                let width = match name {
+                    "d" => 0,
+                    _ => name[1..].parse::<u16>().unwrap() / 8,
+                };
+                let items = split_operands(args)
+                    .into_iter()
+                    .map(|item| match unquote(item) {
+                        Some(bytes) => Ok(DataItem::Bytes(bytes)),
+                        None if width == 0 => {
+                            Err("`#d` only takes strings; use `#d8` or `#d16`".to_string())
+                        }
+                        None => Ok(DataItem::Expr(Expr::parse(item, scope)?)),
+                    })
+                    .collect::<Result<_, String>>()?;
+                StmtKind::Data { width, items }
+            }
+            "res" => StmtKind::Res(expr()?),
+            "align" => StmtKind::Align(expr()?),
+            "addr" => StmtKind::Addr(expr()?),
+            _ => return Err(format!("Unsupported directive `#{name}`")),
+        }));
+    }
+
+    if let Some((name, value)) = split_const(text) {
+        return Ok(Some(StmtKind::Const(
+            qualify(scope, name),
+            Expr::parse(value, scope)?,
+        )));
+    }
+
+    let (mnemonic, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let Some(&(_, opcode, form)) = isa::INSTRS.iter().find(|(m, ..)| *m == mnemonic) else {
+        return Err(format!("Unknown instruction `{mnemonic}`"));
+    };
+    let operands = split_operands(args.trim())
+        .into_iter()
+        .map(|op| parse_operand(op, scope))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !shape_matches(form, &operands) {
+        return Err(format!(
+            "Wrong operands for `{mnemonic}`\nusage: {mnemonic} {}",
+            usage(form)
+        ));
+    }
+    Ok(Some(StmtKind::Instr {
+        mnemonic,
+        opcode,
+        form,
+        operands,
+    }))
+}
+
+fn encode(kind: &StmtKind, here: i64, syms: &Symbols) -> Result<Vec<u8>, String> {
+    let eval = |e: &Expr| syms.eval(e, here);
+    match kind {
+        StmtKind::Instr {
+            mnemonic,
+            opcode,
+            form,
+            operands,
+        } => encode_instr(*opcode, *form, operands, here, &eval)
+            .map_err(|e| format!("{e} (in `{mnemonic}`)")),
+        StmtKind::Data { width, items } => {
+            let mut out = Vec::new();
+            for item in items {
+                match item {
+                    DataItem::Bytes(b) => out.extend_from_slice(b),
+                    DataItem::Expr(e) => {
+                        let bits = *width as u32 * 8;
+                        let v = fit(eval(e)?, bits, Signedness::Either)?;
+                        out.extend_from_slice(&v.to_be_bytes()[8 - *width as usize..]);
+                    }
+                }
+            }
+            Ok(out)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[derive(Default)]
+struct Symbols {
+    labels: HashMap<String, i64>,
+    /// Constants are evaluated when used, so they can refer to labels defined
+    /// later. `$` in a constant is where the constant was defined.
+    consts: HashMap<String, (Expr, i64)>,
+}
+
+impl Symbols {
+    fn eval(&self, e: &Expr, here: i64) -> Result<i64, String> {
+        self.eval_depth(e, here, 0)
+    }
+
+    fn eval_depth(&self, e: &Expr, here: i64, depth: usize) -> Result<i64, String> {
+        e.eval(here, &|name| self.lookup(name, depth))
+    }
+
+    fn lookup(&self, name: &str, depth: usize) -> Result<i64, String> {
+        if let Some(&addr) = self.labels.get(name) {
+            return Ok(addr);
+        }
+        let Some((e, at)) = self.consts.get(name) else {
+            return Err(format!("Unknown label or constant `{name}`"));
+        };
+        if depth >= MAX_DEPTH {
+            return Err(format!("Constant `{name}` is defined in terms of itself"));
+        }
+        self.eval_depth(e, *at, depth + 1)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Signedness {
+    Unsigned,
+    Signed,
+    /// Like customasm's `iN`: anything that fits as either.
+    Either,
+}
+
+/// Checks that `v` fits in `bits` bits and returns its low `bits` bits.
+fn fit(v: i64, bits: u32, signedness: Signedness) -> Result<u64, String> {
+    let (min, max) = match signedness {
+        Signedness::Unsigned => (0, (1 << bits) - 1),
+        Signedness::Signed => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+        Signedness::Either => (-(1 << (bits - 1)), (1 << bits) - 1),
+    };
+    if !(min..=max).contains(&v) {
+        return Err(format!("{v} doesn't fit in {bits} bits"));
+    }
+    Ok(v as u64 & ((1 << bits) - 1))
+}
+
+/// Packs fields MSB-first, the way customasm's `@` does.
+#[derive(Default)]
+struct Bits {
+    value: u64,
+    len: u32,
+}
+
+impl Bits {
+    fn push(&mut self, v: u64, bits: u32) {
+        self.value = (self.value << bits) | (v & ((1 << bits) - 1));
+        self.len += bits;
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let n = (self.len / 8) as usize;
+        self.value.to_be_bytes()[8 - n..].to_vec()
+    }
+}
+
+fn encode_instr(
+    opcode: u8,
+    form: Form,
+    operands: &[Operand],
+    here: i64,
+    eval: &dyn Fn(&Expr) -> Result<i64, String>,
+) -> Result<Vec<u8>, String> {
+    use Operand::{Expr as E, Mem, Reg as R};
+
+    // Jump targets are absolute addresses, stored relative to the instruction.
+    let rel = |e: &Expr| -> Result<u64, String> {
+        let target = fit(eval(e)?, 16, Signedness::Unsigned)? as i64;
+        Ok((target - here) as u64 & 0xFFFF)
+    };
+
+    let mut bits = Bits::default();
+    bits.push(opcode as u64, 6);
+    match (form, operands) {
+        (Form::None, []) => bits.push(0, 2),
+        (Form::Jump, [E(target)]) => {
+            bits.push(rel(target)?, 16);
+            bits.push(0, 2);
+        }
+        (Form::RegJump, [R(r), E(target)]) => {
+            bits.push(*r as u64, 4);
+            bits.push(rel(target)?, 16);
+            bits.push(0, 6);
+        }
+        (Form::Code, [E(code)]) => bits.push(fit(eval(code)?, 10, Signedness::Unsigned)?, 10),
+        (Form::Reg, [R(r)]) => {
+            bits.push(*r as u64, 4);
+            bits.push(0, 6);
+        }
+        (Form::RegReg, [R(a), R(b)]) => {
+            bits.push(*a as u64, 4);
+            bits.push(*b as u64, 4);
+            bits.push(0, 2);
+        }
+        (Form::RegImm16, [R(r), E(imm)]) => {
+            bits.push(*r as u64, 4);
+            bits.push(fit(eval(imm)?, 16, Signedness::Either)?, 16);
+            bits.push(0, 6);
+        }
+        (Form::RegRegReg, [R(a), R(b), R(c)]) => {
+            for r in [a, b, c] {
+                bits.push(*r as u64, 4);
+            }
+            bits.push(0, 6);
+        }
+        (Form::RegRegImm10, [R(a), R(b), E(imm)]) => {
+            bits.push(*a as u64, 4);
+            bits.push(*b as u64, 4);
+            bits.push(fit(eval(imm)?, 10, Signedness::Either)?, 10);
+        }
+        (Form::MulDiv, [R(a), R(b)]) => {
+            bits.push(*a as u64, 4);
+            bits.push(*b as u64, 4);
+            bits.push(0, 10);
+        }
+        (Form::Store, [Mem { offset, base }, R(src)]) => {
+            bits.push(*base as u64, 4);
+            bits.push(*src as u64, 4);
+            bits.push(fit(eval(offset)?, 10, Signedness::Signed)?, 10);
+        }
+        (Form::Load, [R(dst), Mem { offset, base }]) => {
+            bits.push(*dst as u64, 4);
+            bits.push(*base as u64, 4);
+            bits.push(fit(eval(offset)?, 10, Signedness::Signed)?, 10);
+        }
+        _ => unreachable!("operands are checked by `shape_matches`"),
+    }
+    Ok(bits.into_bytes())
+}
+
+fn shape_matches(form: Form, operands: &[Operand]) -> bool {
+    use Operand::{Expr as E, Mem, Reg as R};
+    matches!(
+        (form, operands),
+        (Form::None, [])
+            | (Form::Jump | Form::Code, [E(_)])
+            | (Form::RegJump | Form::RegImm16, [R(_), E(_)])
+            | (Form::Reg, [R(_)])
+            | (Form::RegReg | Form::MulDiv, [R(_), R(_)])
+            | (Form::RegRegReg, [R(_), R(_), R(_)])
+            | (Form::RegRegImm10, [R(_), R(_), E(_)])
+            | (Form::Store, [Mem { .. }, R(_)])
+            | (Form::Load, [R(_), Mem { .. }])
+    )
+}
+
+fn usage(form: Form) -> &'static str {
+    match form {
+        Form::None => "",
+        Form::Jump => "<LABEL>",
+        Form::RegJump => "<REG>, <LABEL>",
+        Form::Code => "<CODE>",
+        Form::Reg => "<REG>",
+        Form::RegReg | Form::MulDiv => "<REG>, <REG>",
+        Form::RegImm16 => "<REG>, <IMM16>",
+        Form::RegRegReg => "<REG>, <REG>, <REG>",
+        Form::RegRegImm10 => "<REG>, <REG>, <IMM10>",
+        Form::Store => "<OFFSET>(<BASE>), <SRC>",
+        Form::Load => "<DST>, <OFFSET>(<BASE>)",
+    }
+}
+
+fn parse_operand(s: &str, scope: &str) -> Result<Operand, String> {
+    if let Some(reg) = parse_reg(s)? {
+        return Ok(Operand::Reg(reg));
+    }
+    if let Some(open) = s.strip_suffix(')').and_then(|s| s.rfind('(')) {
+        if let Some(base) = parse_reg(s[open + 1..s.len() - 1].trim())? {
+            let offset = s[..open].trim();
+            let offset = match offset.is_empty() {
+                true => Expr::Num(0),
+                false => Expr::parse(offset, scope)?,
+            };
+            return Ok(Operand::Mem { offset, base });
+        }
+    }
+    Ok(Operand::Expr(Expr::parse(s, scope)?))
+}
+
+/// `Some` if `s` is a register, an error if it looks like one but isn't.
+fn parse_reg(s: &str) -> Result<Option<u8>, String> {
+    match s.strip_prefix('$') {
+        Some(name) if name.starts_with(|c: char| c.is_ascii_alphabetic()) => isa::reg_number(s)
+            .map(Some)
+            .ok_or_else(|| format!("Unknown register `{s}`")),
+        _ => Ok(None),
+    }
+}
+
+fn is_block_directive(text: &str) -> bool {
+    ["#bankdef", "#ruledef", "#subruledef"]
+        .iter()
+        .any(|d| text.starts_with(d))
+}
+
+/// Splits `name: rest` off the front of a line.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (name, rest) = text.split_once(':')?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    valid.then_some((name, rest))
+}
+
+/// Splits `NAME = value`.
+fn split_const(text: &str) -> Option<(&str, &str)> {
+    let (name, value) = text.split_once('=')?;
+    let name = name.trim();
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    valid.then_some((name, value.trim()))
+}
+
+/// Splits operands at commas outside of parentheses and quotes.
+fn split_operands(s: &str) -> Vec<&str> {
+    if s.is_empty() {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+    parts
+}
+
+/// Removes a `;` comment, minding quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, ';') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// The bytes of a `"string"` with escapes, or `None` if `s` isn't quoted.
+fn unquote(s: &str) -> Option<Vec<u8>> {
+    let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = Vec::new();
+    let mut bytes = inner.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => out.push(unescape_byte(bytes.next()?).ok()?),
+            _ => out.push(b),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM_START: u16 = Memory::ROM_START;
+
+    /// Assembles `src` as if it were a file beside `lark.customasm`.
+    fn assemble(src: &str) -> Result<Assembled, Vec<Diagnostic>> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test.lark");
+        let mut asm = Assembler::default();
+        asm.read_src(&path, src, 0);
+        asm.finish()
+    }
+
+    fn rom(src: &str) -> Vec<u8> {
+        match assemble(src) {
+            Ok(out) => out.rom,
+            Err(errors) => panic!("{:?}", errors.iter().map(|e| &e.msg).collect::<Vec<_>>()),
+        }
+    }
+
+    fn error(src: &str) -> String {
+        match assemble(src) {
+            Ok(_) => panic!("`{src}` assembled"),
+            Err(errors) => errors[0].msg.clone(),
+        }
+    }
+
+    #[test]
+    fn relative_jumps() {
+        let src = "
+            start:
+                j .fwd
+            .back:
+                nop
+            .fwd:
+                jal $ra, .back
+                bt $t0, .fwd
+                bf $t0, end
+                j .back
+            end:
+                halt
+        ";
+        #[rustfmt::skip]
+        let expected = [
+            0x20, 0x00, 0x10,       // j     +4
+            0x08,                   // nop
+            0x28, 0xBF, 0xFF, 0xC0, // jal   $ra, -1
+            0x32, 0x7F, 0xFF, 0x00, // bt    $t0, -4
+            0x3E, 0x40, 0x01, 0xC0, // bf    $t0, +7
+            0x23, 0xFF, 0xCC,       // j     -13
+            0x04,                   // halt
+        ];
+        assert_eq!(rom(src), expected);
+    }
+
+    #[test]
+    fn store_and_load_operand_order() {
+        // `sw` puts the base before the source, `lw` the destination before
+        // the base.
+        assert_eq!(rom("sw 2($sp), $ra"), [0x57, 0xC8, 0x02]);
+        assert_eq!(rom("lw $ra, -2($sp)"), [0x44, 0xBF, 0xFE]);
+        assert_eq!(rom("sb ($a0), $t1"), [0x58, 0xE8, 0x00]);
+    }
+
+    #[test]
+    fn immediate_ranges() {
+        // s10
+        rom("lw $t0, 511($sp)\nlw $t0, -512($sp)");
+        assert_eq!(
+            error("lw $t0, 512($sp)"),
+            "512 doesn't fit in 10 bits (in `lw`)"
+        );
+        assert_eq!(
+            error("sw -513($sp), $t0"),
+            "-513 doesn't fit in 10 bits (in `sw`)"
+        );
+        // i10 takes signed or unsigned.
+        rom("addi $t0, $t0, 1023\naddi $t0, $t0, -512");
+        assert_eq!(
+            error("addi $t0, $t0, 1024"),
+            "1024 doesn't fit in 10 bits (in `addi`)"
+        );
+        assert_eq!(
+            error("ori $t0, $t0, -513"),
+            "-513 doesn't fit in 10 bits (in `ori`)"
+        );
+        // i16
+        assert_eq!(rom("li $t0, -1"), rom("li $t0, 0xFFFF"));
+        rom("li $t0, -32768");
+        assert_eq!(
+            error("li $t0, 65536"),
+            "65536 doesn't fit in 16 bits (in `li`)"
+        );
+        assert_eq!(
+            error("li $t0, -32769"),
+            "-32769 doesn't fit in 16 bits (in `li`)"
+        );
+    }
+
+    #[test]
+    fn local_labels() {
+        let out = assemble(
+            "
+            a:
+            .loop:
+                j .loop
+            b:
+            .loop:
+                j .loop
+                j a.loop
+            ",
+        )
+        .unwrap();
+        assert_eq!(out.labels["a.loop"], ROM_START);
+        assert_eq!(out.labels["b.loop"], ROM_START + 3);
+        // Each `.loop` is the one in its own scope.
+        assert_eq!(
+            out.rom,
+            [0x20, 0x00, 0x00, 0x20, 0x00, 0x00, 0x23, 0xFF, 0xE8]
+        );
+
+        assert_eq!(error("a:\n.x:\n.x:"), "`.x` is defined twice");
+        assert_eq!(
+            error("a:\n.x:\nb:\nj .x"),
+            "Unknown label or constant `b.x` (in `j`)"
+        );
+    }
+
+    #[test]
+    fn data_sizes() {
+        let out = assemble(
+            r#"
+            #d8 1, 2, 3
+            #align 32
+            aligned:
+            #res 5
+            after_res:
+            #d16 0x1234
+            #d "hi\n"
+            end:
+            "#,
+        )
+        .unwrap();
+        assert_eq!(out.labels["aligned"], ROM_START + 4);
+        assert_eq!(out.labels["after_res"], ROM_START + 9);
+        assert_eq!(out.labels["end"], ROM_START + 14);
+        assert_eq!(out.rom[..4], [1, 2, 3, 0]);
+        assert_eq!(out.rom[9..], [0x12, 0x34, b'h', b'i', b'\n']);
+    }
+
+    #[test]
+    fn customasm_constants() {
+        let src = r#"
+            #include "lark.customasm"
+            li $t0, IO_ADDR
+            exn EXN_BREAKPOINT
+        "#;
+        assert_eq!(rom(src), [0x42, 0x40, 0x00, 0x40, 0x00, 0x01]);
+    }
+}
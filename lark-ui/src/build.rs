@@ -0,0 +1,166 @@
+//! Turning a program into a ROM, whatever it's written in: Meadowlark source
+//! goes through `meadowlark::compile`, `.lark` assembly through the built-in
+//! assembler, and a ROM image is used as is.
+//!
+//! Build products go next to the source unless an output directory is given:
+//! `prog.bin`, plus `prog.sym` and `prog.lines` when there's something to
+//! put in them.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use crate::{
+    asm,
+    cli::BuildOpts,
+    debugger::{LineTable, SourceFiles, Symbols},
+    diagnostic::Diagnostic,
+};
+
+pub const EXIT_OK: u8 = 0;
+pub const EXIT_ERROR: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Meadowlark,
+    Lark,
+    Rom,
+}
+
+impl Lang {
+    pub const EXTENSIONS: &'static str = ".meadowlark, .meadow, .lark, .asm, .bin, .rom";
+
+    /// Tells what a file is by its extension.
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "meadowlark" | "meadow" => Some(Lang::Meadowlark),
+            "lark" | "asm" => Some(Lang::Lark),
+            "bin" | "rom" => Some(Lang::Rom),
+            _ => None,
+        }
+    }
+}
+
+/// A ROM ready to load, and the files it came from.
+pub struct Built {
+    pub rom: PathBuf,
+    pub files: SourceFiles,
+}
+
+/// Builds `program` into a ROM in `out_dir` (or next to `program`).
+pub fn build(program: &Path, out_dir: Option<&Path>) -> Result<Built, Vec<Diagnostic>> {
+    let lang = Lang::of(program).ok_or_else(|| {
+        vec![Diagnostic::new(program, 0, "Unknown file extension")
+            .with_note(format!("supported extensions: {}", Lang::EXTENSIONS))]
+    })?;
+    let mut files = SourceFiles::default();
+    let rom = match lang {
+        Lang::Meadowlark => {
+            files.meadowlark_src = Some(program.to_path_buf());
+            compile_meadowlark(program, out_dir)?
+        }
+        Lang::Lark => {
+            files.lark_src = Some(program.to_path_buf());
+            assemble(program, out_dir)?
+        }
+        Lang::Rom => program.to_path_buf(),
+    };
+    files.romfile = Some(rom.clone());
+    Ok(Built { rom, files })
+}
+
+/// Where the build product of `src` with extension `ext` goes.
+fn output_path(src: &Path, out_dir: Option<&Path>, ext: &str) -> PathBuf {
+    let path = src.with_extension(ext);
+    match out_dir {
+        Some(dir) => dir.join(path.file_name().unwrap_or_default()),
+        None => path,
+    }
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), Vec<Diagnostic>> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            vec![Diagnostic::new(
+                dir,
+                0,
+                format!("Error creating directory: {e}"),
+            )]
+        })?;
+    }
+    std::fs::write(path, contents)
+        .map_err(|e| vec![Diagnostic::new(path, 0, format!("Error writing file: {e}"))])
+}
+
+/// Assembles `src`, writing the ROM, symbols and line table. Returns the
+/// ROM's path.
+pub fn assemble(src: &Path, out_dir: Option<&Path>) -> Result<PathBuf, Vec<Diagnostic>> {
+    let assembled = asm::assemble_file(src)?;
+    let rom = output_path(src, out_dir, "bin");
+    write(&rom, &assembled.rom)?;
+
+    let symbols = Symbols::from_labels(assembled.labels);
+    let sym_path = rom.with_extension(Symbols::EXTENSION);
+    symbols
+        .save(&sym_path)
+        .map_err(|e| vec![Diagnostic::new(&sym_path, 0, e)])?;
+
+    let lines = LineTable::from_entries(assembled.lines);
+    let lines_path = rom.with_extension(LineTable::EXTENSION);
+    lines
+        .save(&lines_path)
+        .map_err(|e| vec![Diagnostic::new(&lines_path, 0, e)])?;
+
+    Ok(rom)
+}
+
+/// Compiles `src` with `meadowlark::compile`, which decides where its output
+/// goes; with an `out_dir`, the ROM and whatever sits beside it are copied
+/// there.
+pub fn compile_meadowlark(src: &Path, out_dir: Option<&Path>) -> Result<PathBuf, Vec<Diagnostic>> {
+    let bin = meadowlark::compile(src, false)
+        .map_err(|e| vec![Diagnostic::from_compile_error(src, &e)])?;
+    let Some(out_dir) = out_dir else {
+        return Ok(bin);
+    };
+
+    let rom = output_path(src, Some(out_dir), "bin");
+    for ext in ["bin", Symbols::EXTENSION, LineTable::EXTENSION] {
+        let from = bin.with_extension(ext);
+        if from.exists() {
+            let bytes = std::fs::read(&from).map_err(|e| {
+                vec![Diagnostic::new(
+                    &from,
+                    0,
+                    format!("Error reading file: {e}"),
+                )]
+            })?;
+            write(&rom.with_extension(ext), &bytes)?;
+        }
+    }
+    Ok(rom)
+}
+
+/// Prints diagnostics the way the console shows them.
+pub fn print_diagnostics(diags: &[Diagnostic]) {
+    for diag in diags {
+        for line in diag.render() {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// `lark build`.
+pub fn run(opts: &BuildOpts) -> ExitCode {
+    match build(&opts.program, opts.out_dir.as_deref()) {
+        Ok(built) => {
+            println!("{}", built.rom.display());
+            ExitCode::from(EXIT_OK)
+        }
+        Err(diags) => {
+            print_diagnostics(&diags);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
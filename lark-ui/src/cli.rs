@@ -1,8 +1,11 @@
-//! Defines the `clap` command line interface for `lark-vm`.
+//! Defines the `clap` command line interface for `lark`.
+//!
+//! With no subcommand, `lark` opens the debugger on the files given by flags,
+//! or else on whatever was open last time.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -19,20 +22,111 @@ pub struct Opts {
     #[arg(short, long)]
     pub romfile: Option<PathBuf>,
 
+    /// Instructions per second to run at. Runs as fast as possible if not
+    /// given.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub clock_hz: Option<u32>,
+
     /// Start in debug mode?
     #[arg(short, long)]
     pub debug: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Each subcommand exits with 0 on success and 1 if the program didn't build;
+// `run --headless` and `test` have more to say (see their help).
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compile or assemble a program into a ROM, and print the ROM's path.
+    Build(BuildOpts),
+    /// Build a program if need be, then run it from the start.
+    Run(RunOpts),
+    /// Build a program if need be, then open it in the debugger.
+    Debug(BuildOpts),
+    /// Build a program if need be, then print its disassembly.
+    Disasm(DisasmOpts),
+    /// Run golden-output tests (`.larktest` files) without a terminal.
+    ///
+    /// Exits with 0 if every test passed and 1 otherwise.
+    Test {
+        /// The test files to run.
+        #[arg(required = true)]
+        tests: Vec<PathBuf>,
+
+        /// Where to put build products. Defaults to each program's
+        /// directory.
+        #[arg(short, long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct BuildOpts {
+    /// A Meadowlark (`.meadow`, `.meadowlark`) or assembly (`.lark`, `.asm`)
+    /// source file, or a ROM (`.bin`, `.rom`).
+    pub program: PathBuf,
+
+    /// Where to put build products. Defaults to the source file's directory.
+    #[arg(short, long)]
+    pub out_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct RunOpts {
+    #[command(flatten)]
+    pub build: BuildOpts,
+
+    /// Run without the TUI, printing the VTTY and debug output to stdout.
+    ///
+    /// Exits with 0 if the CPU halted, 1 on any other error, 2 on an illegal
+    /// instruction and 3 if the run timed out or hit `--max-instrs`.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Give up after this many instructions (headless only).
+    #[arg(long)]
+    pub max_instrs: Option<u64>,
+
+    /// Give up after this many seconds (headless only).
+    #[arg(long, default_value = "10", value_parser = parse_seconds)]
+    pub timeout: Duration,
+}
+
+/// A non-negative number of seconds, possibly fractional.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs = s
+        .parse::<f64>()
+        .map_err(|_| format!("`{s}` isn't a number of seconds"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("`{s}` isn't a number of seconds"))
 }
 
-impl Opts {
-    #[expect(unused)]
-    pub fn rom_src_path(&self) -> PathBuf {
-        self.lark_src.clone().unwrap_or_else(|| {
-            self.romfile
-                .clone()
-                .expect("No lark source file provided")
-                .with_extension("")
-                .with_extension("lark")
-        })
+#[derive(Args, Debug)]
+pub struct DisasmOpts {
+    #[command(flatten)]
+    pub build: BuildOpts,
+
+    /// Print `.lark` source that assembles back into the same ROM, instead
+    /// of a listing.
+    #[arg(long)]
+    pub lark: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds() {
+        assert_eq!(parse_seconds("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_seconds("0.25"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_seconds("0"), Ok(Duration::ZERO));
+        for bad in ["-1", "NaN", "inf", "1e300", "ten", ""] {
+            assert_eq!(
+                parse_seconds(bad),
+                Err(format!("`{bad}` isn't a number of seconds"))
+            );
+        }
     }
 }
@@ -0,0 +1,161 @@
+//! Breakpoints and watchpoints managed from the command console.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::isa::{MemAccess, MemAccessKind};
+
+use super::expr::Expr;
+
+pub struct Breakpoint {
+    pub kind: BreakpointKind,
+    pub enabled: bool,
+    pub condition: Option<Condition>,
+    /// How many times the breakpoint was reached with its condition true.
+    pub hits: usize,
+    /// How many of the upcoming hits to ignore before stopping.
+    pub ignore_count: usize,
+}
+
+/// A breakpoint condition along with the text it was parsed from.
+pub struct Condition {
+    pub src: String,
+    pub expr: Expr,
+}
+
+impl Breakpoint {
+    /// Counts a hit, and returns whether the run should stop for it.
+    pub fn record_hit(&mut self) -> bool {
+        self.hits += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+pub enum BreakpointKind {
+    /// Stop before the instruction at this address executes.
+    Code(u16),
+    /// Stop after an instruction touches this range of memory.
+    Watch(Watch),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Watch {
+    pub addr: u16,
+    pub len: u16,
+    pub on: WatchOn,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum WatchOn {
+    /// `watch`
+    Write,
+    /// `rwatch`
+    Read,
+    /// `awatch`
+    Access,
+}
+
+impl Watch {
+    pub fn triggered_by(&self, access: &MemAccess) -> bool {
+        let kind_matches = match self.on {
+            WatchOn::Write => access.kind == MemAccessKind::Write,
+            WatchOn::Read => access.kind == MemAccessKind::Read,
+            WatchOn::Access => true,
+        };
+        let (lo, hi) = (self.addr as u32, self.addr as u32 + self.len as u32);
+        let (access_lo, access_hi) = (access.addr as u32, access.addr as u32 + access.len as u32);
+        kind_matches && access_lo < hi && lo < access_hi
+    }
+}
+
+impl fmt::Display for WatchOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchOn::Write => write!(f, "watchpoint"),
+            WatchOn::Read => write!(f, "read watchpoint"),
+            WatchOn::Access => write!(f, "access watchpoint"),
+        }
+    }
+}
+
+/// Breakpoints and watchpoints share one numbering, starting from 1 in the
+/// order they are created. Numbers are never reused, so `delete 2` followed
+/// by `break` gives you breakpoint 3.
+#[derive(Default)]
+pub struct Breakpoints {
+    last_id: usize,
+    bps: BTreeMap<usize, Breakpoint>,
+}
+
+impl Breakpoints {
+    /// Adds an enabled breakpoint and returns its number.
+    pub fn insert(&mut self, kind: BreakpointKind, condition: Option<Condition>) -> usize {
+        self.last_id += 1;
+        self.bps.insert(
+            self.last_id,
+            Breakpoint {
+                kind,
+                enabled: true,
+                condition,
+                hits: 0,
+                ignore_count: 0,
+            },
+        );
+        self.last_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Breakpoint> {
+        self.bps.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.bps.clear();
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.bps.get_mut(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.bps.iter().map(|(&id, bp)| (id, bp))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bps.is_empty()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = (usize, &BreakpointKind)> {
+        self.iter()
+            .filter(|(_, bp)| bp.enabled)
+            .map(|(id, bp)| (id, &bp.kind))
+    }
+
+    /// Returns the numbers of the enabled code breakpoints at `addr`.
+    pub fn code_at(&self, addr: u16) -> Vec<usize> {
+        self.enabled()
+            .filter_map(|(id, kind)| match kind {
+                BreakpointKind::Code(bp_addr) if *bp_addr == addr => Some(id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the enabled watchpoints triggered by `access`.
+    pub fn watches_hit(&self, access: &MemAccess) -> Vec<(usize, Watch)> {
+        self.enabled()
+            .filter_map(|(id, kind)| match kind {
+                BreakpointKind::Watch(watch) if watch.triggered_by(access) => Some((id, *watch)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn has_watchpoints(&self) -> bool {
+        self.enabled()
+            .any(|(_, kind)| matches!(kind, BreakpointKind::Watch(_)))
+    }
+}
@@ -0,0 +1,598 @@
+//! The console commands a `Debugger` understands.
+
+use std::{fmt::Write, path::Path};
+
+use lark_vm::cpu::MemRw;
+
+use super::{
+    breakpoints::{BreakpointKind, Condition, Watch, WatchOn},
+    expr::Expr,
+    Debugger, Output, Symbols,
+};
+
+impl Debugger {
+    pub fn do_cmd(&mut self, cmd: &str) {
+        match cmd.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
+            ["load" | "l", path] => {
+                self.load(Path::new(path));
+            }
+            ["listing" | "program" | "prog"] => {
+                self.cmd_info("Program:".to_string());
+                let mut line = String::new();
+                let rom = self.cpu.mem.rom.mem.clone();
+                for (i, b) in rom.iter().enumerate() {
+                    line.push_str(&format!("{:02X} ", b));
+                    if i % 16 == 15 {
+                        self.cmd_info(line.clone());
+                        if line == "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 " {
+                            break;
+                        }
+                        line.clear();
+                    }
+                }
+            }
+            ["errors"] => {
+                if self.diagnostics.is_empty() {
+                    self.cmd_info("No errors.".to_string());
+                }
+                for (i, diag) in self.diagnostics.clone().iter().enumerate() {
+                    self.cmd_err(format!("[{}] {diag}", i + 1));
+                }
+            }
+            ["errors", n_str] => {
+                let diag = n_str
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| self.diagnostics.get(n.checked_sub(1)?));
+                match diag {
+                    Some(diag) => self.cmd_err(diag.render().join("\n")),
+                    None => self.cmd_err(format!(
+                        "No error #{n_str} (there are {})",
+                        self.diagnostics.len()
+                    )),
+                }
+            }
+            ["symbols" | "sym"] => {
+                if self.symbols.is_empty() {
+                    self.cmd_info("No symbols loaded.".to_string());
+                }
+                let lines = self
+                    .symbols
+                    .iter()
+                    .map(|(addr, name)| format!("  0x{addr:04X}  {name}"))
+                    .collect::<Vec<_>>();
+                for line in lines {
+                    self.cmd_info(line);
+                }
+            }
+            ["symbols" | "sym", path] => match Symbols::load(Path::new(path)) {
+                Ok(symbols) => {
+                    self.cmd_info(format!("Loaded {} symbols from `{path}`", symbols.len()));
+                    self.symbols = symbols;
+                    self.publish_loaded();
+                }
+                Err(e) => self.cmd_err(e),
+            },
+            ["watch-src", "on"] => self.set_watch_src(true),
+            ["watch-src", "off"] => self.set_watch_src(false),
+            // Reset the CPU and clear the virtual terminal.
+            ["reset"] => {
+                self.reset_cpu();
+            }
+            ["snapshot", "save", name] => {
+                self.snapshot_save(name);
+            }
+            ["snapshot", "load", name] => {
+                self.snapshot_load(name);
+            }
+            ["snapshot", "list"] => {
+                self.snapshot_list();
+            }
+            ["registers" | "regs" | "reg"] => {
+                let mut lines = String::new();
+                write!(&mut lines, "{}", self.cpu.regs).unwrap();
+                for line in lines.lines() {
+                    self.cmd_log(line);
+                }
+            }
+            ["run"] => {
+                self.run();
+            }
+            ["backtrace" | "bt"] => {
+                self.print_backtrace();
+            }
+            ["next" | "n"] => {
+                self.step_over();
+            }
+            ["finish" | "fin"] => {
+                self.finish();
+            }
+            ["step-line" | "sl"] => {
+                self.step_line(false);
+            }
+            ["next-line" | "nl"] => {
+                self.step_line(true);
+            }
+            ["reverse-step" | "rs"] => {
+                self.reverse_step();
+            }
+            ["reverse-continue" | "rc"] => {
+                self.reverse_continue();
+            }
+            ["until" | "u", loc] => {
+                let Some(addr) = self.parse_location(loc) else {
+                    return;
+                };
+                self.run_until(addr);
+            }
+            ["speed"] => match self.clock_hz {
+                Some(hz) => self.cmd_info(format!("Clock rate: {hz} Hz")),
+                None => self.cmd_info("Clock rate: max".to_string()),
+            },
+            ["speed", "max"] => {
+                self.set_clock_hz(None);
+                self.cmd_info("Clock rate: max".to_string());
+            }
+            ["speed", hz_str] => {
+                let Some(hz) = hz_str.parse::<u32>().ok().filter(|&hz| hz > 0) else {
+                    self.cmd_err(format!("Invalid clock rate: `{hz_str}`"));
+                    self.cmd_info("  - Usage: speed <HZ|max>".to_string());
+                    return;
+                };
+                self.set_clock_hz(Some(hz));
+                self.cmd_info(format!("Clock rate: {hz} Hz"));
+            }
+            ["break" | "b", args @ ..] if !args.is_empty() => {
+                let (loc, cond) = split_condition(args);
+                let [loc] = loc else {
+                    self.cmd_err("Usage: break <ADDR|LABEL> [if <EXPR>]".to_string());
+                    return;
+                };
+                let Some(addr) = self.parse_location(loc) else {
+                    return;
+                };
+                let Some(condition) = self.parse_condition(cond) else {
+                    return;
+                };
+                let id = self
+                    .breakpoints
+                    .insert(BreakpointKind::Code(addr), condition);
+                self.cmd_info(format!(
+                    "Breakpoint {id} at {}",
+                    self.symbols.annotate(addr)
+                ));
+            }
+            ["toggle-break" | "tb", loc] => {
+                let Some(addr) = self.parse_location(loc) else {
+                    return;
+                };
+                self.toggle_breakpoint(addr);
+            }
+            ["set", rest @ ..] if !rest.is_empty() => self.set(&rest.join(" "), false),
+            ["set.b", rest @ ..] if !rest.is_empty() => self.set(&rest.join(" "), true),
+            ["fill", addr, ":+", len, byte] => self.fill(addr, len, byte),
+            ["copy", src, dst, len] => self.copy(src, dst, len),
+            ["mem", "follow", "off"] => self.mem_unfollow(),
+            ["mem", "follow", expr @ ..] if !expr.is_empty() => self.mem_follow(&expr.join(" ")),
+            ["mem", expr @ ..] if !expr.is_empty() => self.mem_goto(&expr.join(" ")),
+            ["goto", loc] => {
+                let Some(addr) = self.parse_location(loc) else {
+                    return;
+                };
+                self.output.push(Output::Goto(addr));
+            }
+            [cmd @ ("watch" | "rwatch" | "awatch"), args @ ..] => {
+                let on = match *cmd {
+                    "watch" => WatchOn::Write,
+                    "rwatch" => WatchOn::Read,
+                    _ => WatchOn::Access,
+                };
+                let (range, cond) = split_condition(args);
+                let Some((addr, len)) = self.parse_watch_range(range) else {
+                    return;
+                };
+                let Some(condition) = self.parse_condition(cond) else {
+                    return;
+                };
+                let id = self
+                    .breakpoints
+                    .insert(BreakpointKind::Watch(Watch { addr, len, on }), condition);
+                self.cmd_info(format!(
+                    "{} {id}: 0x{addr:04X}:+{len}",
+                    capitalize(&on.to_string())
+                ));
+            }
+            ["delete" | "d"] => {
+                self.breakpoints.clear();
+                self.cmd_info("Deleted all breakpoints.".to_string());
+            }
+            ["delete" | "d", id_str] => {
+                let Some(id) = self.parse_breakpoint_id(id_str) else {
+                    return;
+                };
+                self.breakpoints.remove(id);
+                self.cmd_info(format!("Deleted breakpoint {id}."));
+            }
+            [cmd @ ("disable" | "enable"), id_str] => {
+                let Some(id) = self.parse_breakpoint_id(id_str) else {
+                    return;
+                };
+                let enabled = *cmd == "enable";
+                if let Some(bp) = self.breakpoints.get_mut(id) {
+                    bp.enabled = enabled;
+                }
+                self.cmd_info(format!("Breakpoint {id} {cmd}d."));
+            }
+            ["condition", id_str, cond @ ..] => {
+                let Some(id) = self.parse_breakpoint_id(id_str) else {
+                    return;
+                };
+                let Some(condition) = self.parse_condition((!cond.is_empty()).then_some(cond))
+                else {
+                    return;
+                };
+                let msg = match &condition {
+                    Some(c) => format!("Breakpoint {id} now stops only if {}.", c.src),
+                    None => format!("Breakpoint {id} is now unconditional."),
+                };
+                if let Some(bp) = self.breakpoints.get_mut(id) {
+                    bp.condition = condition;
+                }
+                self.cmd_info(msg);
+            }
+            ["ignore", id_str, count_str] => {
+                let Some(id) = self.parse_breakpoint_id(id_str) else {
+                    return;
+                };
+                let Ok(count) = count_str.parse::<usize>() else {
+                    self.cmd_err(format!("Invalid count: `{count_str}`"));
+                    return;
+                };
+                if let Some(bp) = self.breakpoints.get_mut(id) {
+                    bp.ignore_count = count;
+                }
+                self.cmd_info(format!("Will ignore next {count} hits of breakpoint {id}."));
+            }
+            ["info", "breakpoints" | "break" | "b"] => {
+                if self.breakpoints.is_empty() {
+                    self.cmd_info("No breakpoints.".to_string());
+                    return;
+                }
+                let mut lines = vec!["Num  Type               Enb  Address".to_string()];
+                for (id, bp) in self.breakpoints.iter() {
+                    let enb = if bp.enabled { 'y' } else { 'n' };
+                    let (ty, addr) = match &bp.kind {
+                        BreakpointKind::Code(addr) => {
+                            ("breakpoint".to_string(), self.symbols.annotate(*addr))
+                        }
+                        BreakpointKind::Watch(w) => {
+                            (w.on.to_string(), format!("0x{:04X}:+{}", w.addr, w.len))
+                        }
+                    };
+                    lines.push(format!("{id:<4} {ty:<18} {enb:<4} {addr}"));
+                    if let Some(cond) = &bp.condition {
+                        lines.push(format!("     stop only if {}", cond.src));
+                    }
+                    if bp.hits > 0 {
+                        lines.push(format!("     already hit {} time(s)", bp.hits));
+                    }
+                    if bp.ignore_count > 0 {
+                        lines.push(format!("     will ignore next {} hit(s)", bp.ignore_count));
+                    }
+                }
+                for line in lines {
+                    self.cmd_info(line);
+                }
+            }
+            [] | ["step" | "s"] => {
+                self.step();
+            }
+            ["hexdump" | "x"] => {
+                self.cmd_info("Hexdump of ROM:".to_string());
+                let mut line = String::new();
+                let rom = self.cpu.mem.rom.mem.clone();
+                for (i, b) in rom.iter().enumerate() {
+                    line.push_str(&format!("{b:02X} "));
+                    if i % 16 == 7 {
+                        line.push_str("   ");
+                    }
+                    if i % 16 == 15 {
+                        self.cmd_info(format!("{i:04X} | {line}"));
+                        line.clear();
+                    }
+                }
+            }
+            ["print" | "p", expr @ ..] if !expr.is_empty() => {
+                let src = expr.join(" ");
+                let Some(value) = self.eval_expr(&src) else {
+                    return;
+                };
+                let signed = value as i16;
+                self.cmd_info(format!(
+                    "{src} = {value}, {signed:+}, 0x{value:04X}, 0b{value:016b}"
+                ));
+            }
+            // Examine word:
+            ["x" | "x/w", addr_str] => {
+                let Some(addr) = self.eval_expr(addr_str) else {
+                    return;
+                };
+                let word = self.cpu.mem.read_s16(addr);
+                let unsigned = word.as_u16();
+                let signed = word.as_i16();
+                self.cmd_info(format!(
+                    "(16-bit) {addr_str}: {unsigned:5}, {signed:+5}, 0x{unsigned:04X}, 0b{unsigned:016b}"
+                ));
+            }
+            // Examine byte:
+            ["x/b", addr_str] => {
+                let Some(addr) = self.eval_expr(addr_str) else {
+                    return;
+                };
+                let byte = self.cpu.mem.read_u8(addr);
+                let ch = byte as char;
+                let mut msg = format!(" (8-bit) {addr_str}: {byte:3}, 0x{byte:02X}, 0b{byte:08b}");
+                if ch.is_ascii_graphic() || ch.is_ascii_whitespace() {
+                    write!(&mut msg, ", {:?}", ch).unwrap();
+                }
+                self.cmd_info(msg);
+            }
+            ["hexdump" | "x", lo, "..", hi] => {
+                self.cmd_info("Hexdump of ROM:".to_string());
+                let mut line = String::new();
+                let Some(lo) = parse_number(lo) else {
+                    self.cmd_err(format!("Invalid low address: `{lo}`"));
+                    return;
+                };
+                let Some(hi) = parse_number(hi) else {
+                    self.cmd_err(format!("Invalid high address: `{hi}`"));
+                    return;
+                };
+                for i in lo..(hi - lo) {
+                    let b = self.cpu.mem.read_u8(i);
+                    line.push_str(&format!("{b:02X} "));
+                    if i % 16 == 7 {
+                        line.push_str("   ");
+                    }
+                    if i % 16 == 15 {
+                        self.cmd_info(format!("{i:04X} | {line}"));
+                        line.clear();
+                    }
+                }
+                if !line.is_empty() {
+                    self.cmd_info(line);
+                }
+            }
+            ["hexdump" | "x", base, ":+", len] => {
+                self.cmd_info("Hexdump of ROM:".to_string());
+                let mut line = String::new();
+                let Some(base) = parse_number(base) else {
+                    self.cmd_err(format!("Invalid base address: `{base}`"));
+                    return;
+                };
+                let Some(len) = parse_number(len) else {
+                    self.cmd_err(format!("Invalid length: `{len}`"));
+                    return;
+                };
+                for i in base..(base + len) {
+                    let b = self.cpu.mem.read_u8(i);
+                    line.push_str(&format!("{b:02X} "));
+                    if i % 16 == 7 {
+                        line.push_str("   ");
+                    }
+                    if i % 16 == 15 {
+                        self.cmd_info(format!("{i:04X} | {line}"));
+                        line.clear();
+                    }
+                }
+                if !line.is_empty() {
+                    self.cmd_info(line);
+                }
+            }
+            ["help" | "h" | "?"] => {
+                self.cmd_info("Commands:".to_string());
+                self.cmd_info("  - load <PATH> (l)".to_string());
+                self.cmd_info("  - step (s, ENTER)".to_string());
+                self.cmd_info("  - next (n)".to_string());
+                self.cmd_info("  - finish (fin)".to_string());
+                self.cmd_info("  - step-line (sl), next-line (nl)".to_string());
+                self.cmd_info("  - until (u) <ADDR|LABEL>".to_string());
+                self.cmd_info("  - run".to_string());
+                self.cmd_info("  - reverse-step (rs), reverse-continue (rc)".to_string());
+                self.cmd_info("  - speed [<HZ>|max]".to_string());
+                self.cmd_info("  - break (b) <ADDR|LABEL> [if <EXPR>]".to_string());
+                self.cmd_info("  - toggle-break (tb) <ADDR|LABEL>".to_string());
+                self.cmd_info("  - delete (d) [<N>]".to_string());
+                self.cmd_info("  - disable <N>, enable <N>".to_string());
+                self.cmd_info("  - condition <N> [<EXPR>]".to_string());
+                self.cmd_info("  - ignore <N> <COUNT>".to_string());
+                self.cmd_info("  - watch, rwatch, awatch <ADDR>[:+<LEN>] [if <EXPR>]".to_string());
+                self.cmd_info("  - info breakpoints (info b)".to_string());
+                self.cmd_info("  - goto <ADDR|LABEL>".to_string());
+                self.cmd_info("  - errors [<N>]".to_string());
+                self.cmd_info("  - symbols (sym) [<PATH>]".to_string());
+                self.cmd_info("  - watch-src on|off".to_string());
+                self.cmd_info("  - reset".to_string());
+                self.cmd_info("  - snapshot save <NAME>, snapshot load <NAME>".to_string());
+                self.cmd_info("  - snapshot list".to_string());
+                self.cmd_info("  - registers (regs)".to_string());
+                self.cmd_info("  - backtrace (bt)".to_string());
+                self.cmd_info("  - print (p) <EXPR>".to_string());
+                self.cmd_info("  - x, x/w, x/b <EXPR>".to_string());
+                self.cmd_info("  - set <REG|[ADDR]> = <EXPR>, set.b [<ADDR>] = <EXPR>".to_string());
+                self.cmd_info(
+                    "  - fill <ADDR> :+ <LEN> <BYTE>, copy <SRC> <DST> <LEN>".to_string(),
+                );
+                self.cmd_info("  - mem <EXPR>, mem follow <EXPR>|off".to_string());
+                self.cmd_info("  - program (prog, listing)".to_string());
+                self.cmd_info("  - hexdump (x)".to_string());
+                self.cmd_info("  - hexdump (x) <LOW> .. <HIGH>".to_string());
+                self.cmd_info("  - hexdump (x) <BASE> :+ <LEN>".to_string());
+                self.cmd_info("  - reg-delta on|off".to_string());
+                self.cmd_info("  - clearhist".to_string());
+                self.cmd_info("  - help (h, ?)".to_string());
+                self.cmd_info("  - quit (q)".to_string());
+            }
+            _ => {
+                self.cmd_err(format!("Unknown command: `{}`", cmd));
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.cmd_log("Stepping...".to_string());
+        self.step_watched();
+    }
+
+    pub fn run(&mut self) {
+        self.cmd_log(format!(
+            "Running `{}`...",
+            self.romfile
+                .as_ref()
+                .or(self.lark_src.as_ref())
+                .or(self.meadowlark_src.as_ref())
+                .map(|p| p.display().to_string())
+                .unwrap_or("<unknown source file>".to_string())
+        ));
+        self.cpu_run_till_breakpoint = true;
+        self.resume_pc = Some(self.cpu.pc);
+        self.step_goal = None;
+    }
+
+    /// Deletes the code breakpoints at `addr`, or sets one if there are none.
+    fn toggle_breakpoint(&mut self, addr: u16) {
+        let ids = self
+            .breakpoints
+            .iter()
+            .filter(|(_, bp)| matches!(bp.kind, BreakpointKind::Code(a) if a == addr))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            let id = self.breakpoints.insert(BreakpointKind::Code(addr), None);
+            self.cmd_info(format!(
+                "Breakpoint {id} at {}",
+                self.symbols.annotate(addr)
+            ));
+        }
+        for id in ids {
+            self.breakpoints.remove(id);
+            self.cmd_info(format!("Deleted breakpoint {id}."));
+        }
+    }
+
+    /// Resolves a code location given as an address or label, reporting an
+    /// error if it can't be resolved.
+    fn parse_location(&mut self, s: &str) -> Option<u16> {
+        if let Some(addr) = parse_number(s).or_else(|| self.symbols.lookup(s)) {
+            return Some(addr);
+        }
+        self.cmd_err(format!("Unknown label: `{s}`"));
+        if self.symbols.is_empty() {
+            self.cmd_info("  - No symbol table is loaded, use an address instead".to_string());
+        }
+        None
+    }
+
+    /// Parses `ADDR`, `ADDR:+LEN` or `ADDR :+ LEN`. Watches a word by default.
+    fn parse_watch_range(&mut self, args: &[&str]) -> Option<(u16, u16)> {
+        let (addr_str, len_str) = match args {
+            [arg] => match arg.split_once(":+") {
+                Some((addr, len)) => (addr, Some(len)),
+                None => (*arg, None),
+            },
+            [addr, ":+", len] => (*addr, Some(*len)),
+            _ => {
+                self.cmd_err("Usage: watch <ADDR>[:+<LEN>]".to_string());
+                return None;
+            }
+        };
+        let Some(addr) = parse_number(addr_str).or_else(|| self.symbols.lookup(addr_str)) else {
+            self.cmd_err(format!("Invalid address: `{addr_str}`"));
+            return None;
+        };
+        let len = match len_str.map(parse_number) {
+            None => 2,
+            Some(Some(len)) if len > 0 => len,
+            Some(_) => {
+                self.cmd_err(format!("Invalid length: `{}`", len_str.unwrap()));
+                return None;
+            }
+        };
+        Some((addr, len))
+    }
+
+    /// Parses the words after `if` in a breakpoint command. `Some(None)` means
+    /// there was no condition, `None` means it failed to parse.
+    fn parse_condition(&mut self, words: Option<&[&str]>) -> Option<Option<Condition>> {
+        let Some(words) = words else {
+            return Some(None);
+        };
+        let src = words.join(" ");
+        match Expr::parse(&src, &self.cpu, &self.symbols) {
+            Ok(expr) => Some(Some(Condition { src, expr })),
+            Err(e) => {
+                self.cmd_err(format!("Invalid condition: {e}"));
+                None
+            }
+        }
+    }
+
+    pub(super) fn eval_expr(&mut self, src: &str) -> Option<u16> {
+        match self.eval(src) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.cmd_err(e);
+                None
+            }
+        }
+    }
+
+    fn parse_breakpoint_id(&mut self, s: &str) -> Option<usize> {
+        let Ok(id) = s.parse::<usize>() else {
+            self.cmd_err(format!("Invalid breakpoint number: `{s}`"));
+            return None;
+        };
+        if self.breakpoints.get_mut(id).is_none() {
+            self.cmd_err(format!("No breakpoint number {id}."));
+            return None;
+        }
+        Some(id)
+    }
+}
+
+/// Splits command arguments at an `if`, returning the words before it and the
+/// condition words after it.
+fn split_condition<'a, 'b>(args: &'b [&'a str]) -> (&'b [&'a str], Option<&'b [&'a str]>) {
+    match args.iter().position(|&w| w == "if") {
+        Some(i) => (&args[..i], Some(&args[i + 1..])),
+        None => (args, None),
+    }
+}
+
+pub fn parse_number(s: &str) -> Option<u16> {
+    if let Some(stripped) = s.strip_prefix("0b") {
+        u16::from_str_radix(stripped, 2).ok()
+    } else if let Some(stripped) = s.strip_prefix("0o") {
+        return u16::from_str_radix(stripped, 8).ok();
+    } else if let Some(stripped) = s.strip_prefix("0x") {
+        return u16::from_str_radix(stripped, 16).ok();
+    } else {
+        return s.parse().ok();
+    }
+}
+
+pub(super) fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(super) fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
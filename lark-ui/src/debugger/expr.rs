@@ -0,0 +1,458 @@
+//! A small expression language over machine state, used for breakpoint
+//! conditions and anywhere else the console takes a value.
+//!
+//! Values are 16-bit words: arithmetic wraps and comparisons are signed, as
+//! with `tlt`/`tge`. Registers are written the way `Reg` displays them (`$a0`,
+//! `$sp`) along with `$lo`, `$hi` and `$pc`, and `[ADDR]` reads the word at
+//! `ADDR`, unless it's in the MMIO page. Numbers are anything `parse_number` accepts or a character like
+//! `'A'` or `'\n'`, and any other name is looked up in the symbol table.
+
+use std::fmt;
+
+use lark_vm::cpu::{regs::Reg, Cpu, MemRw};
+
+use super::{commands::parse_number, Symbols, MMIO_START};
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Num(u16),
+    Reg(Reg),
+    Lo,
+    Hi,
+    Pc,
+    Deref(Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum UnOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BinOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Binary operators from lowest to highest precedence. Within a level, longer
+/// operators come first so `<=` isn't read as `<`.
+const PRECEDENCE: &[&[(&str, BinOp)]] = &[
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("|", BinOp::BitOr)],
+    &[("^", BinOp::BitXor)],
+    &[("&", BinOp::BitAnd)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[
+        ("<=", BinOp::Le),
+        (">=", BinOp::Ge),
+        ("<", BinOp::Lt),
+        (">", BinOp::Gt),
+    ],
+    &[("<<", BinOp::Shl), (">>", BinOp::Shr)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+];
+
+const PUNCT: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+#[derive(Debug, Clone)]
+enum Tok<'a> {
+    Num(u16),
+    Ident(&'a str),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Tok<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Num(n) => write!(f, "{n}"),
+            Tok::Ident(name) => write!(f, "{name}"),
+            Tok::Punct(p) => write!(f, "{p}"),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Tok<'_>>, String> {
+    let mut toks = Vec::new();
+    let mut rest = src.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        let word_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.'))
+            .unwrap_or(rest.len());
+
+        if ch == '\'' {
+            let (n, len) = char_literal(rest)?;
+            toks.push(Tok::Num(n));
+            rest = &rest[len..];
+        } else if ch.is_ascii_digit() {
+            let word = &rest[..word_len];
+            let n = parse_number(word).ok_or_else(|| format!("Invalid number: `{word}`"))?;
+            toks.push(Tok::Num(n));
+            rest = &rest[word_len..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' || ch == '$' {
+            toks.push(Tok::Ident(&rest[..word_len]));
+            rest = &rest[word_len..];
+        } else if let Some(p) = PUNCT.iter().find(|p| rest.starts_with(**p)) {
+            toks.push(Tok::Punct(p));
+            rest = &rest[p.len()..];
+        } else {
+            return Err(format!("Unexpected character `{ch}` in expression"));
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(toks)
+}
+
+/// The value of the character literal `src` starts with, and its length.
+fn char_literal(src: &str) -> Result<(u16, usize), String> {
+    let mut chars = src.char_indices().skip(1);
+    let ch = match chars.next() {
+        Some((_, '\\')) => match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '0')) => '\0',
+            Some((_, ch @ ('\\' | '\''))) => ch,
+            _ => return Err("Invalid escape in character literal".to_string()),
+        },
+        Some((_, ch)) if ch.is_ascii() && ch != '\'' => ch,
+        _ => return Err("Invalid character literal".to_string()),
+    };
+    match chars.next() {
+        Some((i, '\'')) => Ok((ch as u16, i + 1)),
+        _ => Err("Expected `'` to end character literal".to_string()),
+    }
+}
+
+struct Parser<'a, 'c> {
+    toks: Vec<Tok<'a>>,
+    pos: usize,
+    cpu: &'c Cpu,
+    symbols: &'c Symbols,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Option<&Tok<'a>> {
+        self.toks.get(self.pos)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(format!("Expected `{punct}` in expression"))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+
+        let mut lhs = self.binary(level + 1)?;
+        'outer: loop {
+            for (punct, op) in ops.iter() {
+                if self.eat(punct) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = if self.eat("-") {
+            UnOp::Neg
+        } else if self.eat("!") {
+            UnOp::Not
+        } else if self.eat("~") {
+            UnOp::BitNot
+        } else {
+            return self.primary();
+        };
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let e = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+
+        if self.eat("[") {
+            let e = self.binary(0)?;
+            self.expect("]")?;
+            return Ok(Expr::Deref(Box::new(e)));
+        }
+
+        match self.peek().cloned() {
+            Some(Tok::Num(n)) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            }
+            Some(Tok::Ident(name)) => {
+                self.pos += 1;
+                self.ident(name)
+            }
+            Some(Tok::Punct(p)) => Err(format!("Unexpected `{p}` in expression")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+
+    fn ident(&self, name: &str) -> Result<Expr, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "$lo" => return Ok(Expr::Lo),
+            "$hi" => return Ok(Expr::Hi),
+            "$pc" => return Ok(Expr::Pc),
+            _ => {}
+        }
+
+        if !name.starts_with('$') {
+            return self
+                .symbols
+                .lookup(name)
+                .map(Expr::Num)
+                .ok_or_else(|| format!("Unknown symbol: `{name}`"));
+        }
+
+        self.cpu
+            .regs
+            .iter()
+            .map(|(reg, _)| reg)
+            .find(|reg| reg.to_string() == name)
+            .map(Expr::Reg)
+            .ok_or_else(|| format!("Unknown register: `{name}`"))
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str, cpu: &Cpu, symbols: &Symbols) -> Result<Self, String> {
+        let mut parser = Parser {
+            toks: tokenize(src)?,
+            pos: 0,
+            cpu,
+            symbols,
+        };
+        let e = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(e),
+            Some(tok) => Err(format!("Unexpected `{tok}` after end of expression")),
+        }
+    }
+
+    pub fn eval(&self, cpu: &mut Cpu) -> Result<u16, String> {
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Reg(reg) => cpu
+                .regs
+                .iter()
+                .find(|(r, _)| r == reg)
+                .map_or(0, |(_, value)| value.as_u16()),
+            Expr::Lo => cpu.lo.as_u16(),
+            Expr::Hi => cpu.hi.as_u16(),
+            Expr::Pc => cpu.pc,
+            Expr::Deref(addr) => {
+                let addr = addr.eval(cpu)?;
+                // Reading a device register can take a key press or change
+                // the device, so conditions and `print` mustn't.
+                if addr >= MMIO_START - 1 {
+                    return Err(format!(
+                        "Won't read 0x{addr:04X} from the MMIO page, where reads have side effects"
+                    ));
+                }
+                cpu.mem.read_s16(addr).as_u16()
+            }
+            Expr::Unary(op, e) => {
+                let x = e.eval(cpu)?;
+                match op {
+                    UnOp::Neg => x.wrapping_neg(),
+                    UnOp::Not => (x == 0) as u16,
+                    UnOp::BitNot => !x,
+                }
+            }
+            Expr::Binary(BinOp::And, lhs, rhs) => {
+                (lhs.eval(cpu)? != 0 && rhs.eval(cpu)? != 0) as u16
+            }
+            Expr::Binary(BinOp::Or, lhs, rhs) => {
+                (lhs.eval(cpu)? != 0 || rhs.eval(cpu)? != 0) as u16
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (x, y) = (lhs.eval(cpu)?, rhs.eval(cpu)?);
+                let (sx, sy) = (x as i16, y as i16);
+                match op {
+                    BinOp::BitOr => x | y,
+                    BinOp::BitXor => x ^ y,
+                    BinOp::BitAnd => x & y,
+                    BinOp::Eq => (x == y) as u16,
+                    BinOp::Ne => (x != y) as u16,
+                    BinOp::Lt => (sx < sy) as u16,
+                    BinOp::Le => (sx <= sy) as u16,
+                    BinOp::Gt => (sx > sy) as u16,
+                    BinOp::Ge => (sx >= sy) as u16,
+                    BinOp::Shl => x.wrapping_shl(y as u32),
+                    BinOp::Shr => x.wrapping_shr(y as u32),
+                    BinOp::Add => x.wrapping_add(y),
+                    BinOp::Sub => x.wrapping_sub(y),
+                    BinOp::Mul => x.wrapping_mul(y),
+                    BinOp::Div | BinOp::Rem if y == 0 => {
+                        return Err("Division by zero in expression".to_string())
+                    }
+                    BinOp::Div => sx.wrapping_div(sy) as u16,
+                    BinOp::Rem => sx.wrapping_rem(sy) as u16,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lark_vm::cpu::{regs::Reg, MemRw};
+
+    use crate::debugger::{Debugger, SourceFiles, Symbols};
+
+    fn debugger() -> Debugger {
+        let mut dbg = Debugger::new(SourceFiles::default());
+        dbg.symbols = Symbols::from_labels([
+            ("main".to_owned(), 0x0800),
+            ("main.loop".to_owned(), 0x0806),
+        ]);
+        dbg
+    }
+
+    fn reg(dbg: &Debugger, name: &str) -> Reg {
+        let mut regs = dbg.cpu.regs.iter().map(|(reg, _)| reg);
+        regs.find(|reg| reg.to_string() == name).unwrap()
+    }
+
+    fn eval(src: &str) -> Result<u16, String> {
+        debugger().eval(src)
+    }
+
+    fn err(src: &str) -> String {
+        eval(src).unwrap_err()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(eval("42"), Ok(42));
+        assert_eq!(eval("0x2A"), Ok(42));
+        assert_eq!(eval("0b101010"), Ok(42));
+        assert_eq!(eval("0o52"), Ok(42));
+        assert_eq!(eval("'*'"), Ok(42));
+        assert_eq!(eval(r"'\n'"), Ok(10));
+        assert_eq!(eval(r"'\''"), Ok(39));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 | 6 & 3"), Ok(3));
+        assert_eq!(eval("1 << 4 >> 2"), Ok(4));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("2 + 2 == 4 && 1 < 2"), Ok(1));
+        assert_eq!(eval("0 || 3 != 3"), Ok(0));
+        assert_eq!(eval("!5"), Ok(0));
+        assert_eq!(eval("!0"), Ok(1));
+        assert_eq!(eval("~0"), Ok(0xFFFF));
+    }
+
+    #[test]
+    fn words_are_signed_and_wrap() {
+        assert_eq!(eval("0 - 1"), Ok(0xFFFF));
+        assert_eq!(eval("0xFFFF + 2"), Ok(1));
+        assert_eq!(eval("-1 < 0"), Ok(1));
+        assert_eq!(eval("0xFFFF > 1"), Ok(0));
+        assert_eq!(eval("-7 / 2"), Ok(-3i16 as u16));
+        assert_eq!(eval("-7 % 2"), Ok(-1i16 as u16));
+    }
+
+    #[test]
+    fn machine_state() {
+        let mut dbg = debugger();
+        let (a0, sp) = (reg(&dbg, "$a0"), reg(&dbg, "$sp"));
+        dbg.cpu.regs.set(a0, 5u16.into());
+        dbg.cpu.regs.set(sp, 0x1000u16.into());
+        dbg.cpu.lo = 0xBEEFu16.into();
+        dbg.cpu.mem.write_s16(0x1000, 0x1234u16.into());
+
+        assert_eq!(dbg.eval("$a0 * 2"), Ok(10));
+        assert_eq!(dbg.eval("$lo"), Ok(0xBEEF));
+        assert_eq!(dbg.eval("$LO"), Ok(0xBEEF));
+        assert_eq!(dbg.eval("$pc"), Ok(dbg.cpu.pc));
+        assert_eq!(dbg.eval("[$sp]"), Ok(0x1234));
+        assert_eq!(dbg.eval("[$sp - 2 + 2] & 0xFF"), Ok(0x34));
+        assert_eq!(dbg.eval("main.loop - main"), Ok(6));
+    }
+
+    #[test]
+    fn no_mmio_reads() {
+        assert_eq!(eval("[0xEFFE]"), Ok(0));
+        assert_eq!(
+            err("[0xEFFF]"),
+            "Won't read 0xEFFF from the MMIO page, where reads have side effects"
+        );
+        assert_eq!(
+            err("0 || [0xF000 + 2]"),
+            "Won't read 0xF002 from the MMIO page, where reads have side effects"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(err(""), "Unexpected end of expression");
+        assert_eq!(err("1 +"), "Unexpected end of expression");
+        assert_eq!(err("1 2"), "Unexpected `2` after end of expression");
+        assert_eq!(err("(1"), "Expected `)` in expression");
+        assert_eq!(err("[1"), "Expected `]` in expression");
+        assert_eq!(err("* 2"), "Unexpected `*` in expression");
+        assert_eq!(err("1 @ 2"), "Unexpected character `@` in expression");
+        assert_eq!(err("0x"), "Invalid number: `0x`");
+        assert_eq!(err("'ab'"), "Expected `'` to end character literal");
+        assert_eq!(err(r"'\q'"), "Invalid escape in character literal");
+        assert_eq!(err("$q0"), "Unknown register: `$q0`");
+        assert_eq!(err("nowhere"), "Unknown symbol: `nowhere`");
+        assert_eq!(err("1 / 0"), "Division by zero in expression");
+    }
+}
@@ -0,0 +1,254 @@
+//! Recorded execution history for `reverse-step` and `reverse-continue`.
+//!
+//! Before each instruction we note `pc`, `lo`/`hi`, the registers, the
+//! interrupt state and the bytes the instruction is about to store to, then
+//! keep only what the instruction actually changed. Stores to the VTTY go
+//! through memory like any other, so undoing them rewinds the screen too.
+//! Side effects outside the machine (MMIO logging, key presses) aren't undone,
+//! and an interrupt raised while an instruction ran is lost by undoing it.
+
+use std::collections::VecDeque;
+
+use lark_vm::{
+    cpu::{interrupts::Interrupt, regs::Reg, Cpu, MemRw},
+    utils::s16,
+};
+
+use crate::isa::{MemAccess, MemAccessKind};
+
+use super::{commands::capitalize, Debugger};
+
+/// How many instructions can be undone. Most deltas are a few registers and
+/// at most two bytes of memory, so this costs a few MiB at most.
+const HISTORY_LEN: usize = 1 << 16;
+
+const REG_COUNT: usize = 16;
+
+/// The interrupts waiting to be taken, oldest first.
+fn pending_interrupts(cpu: &mut Cpu) -> Vec<u8> {
+    let pending = cpu.pending_interrupts();
+    pending.into_iter().map(|Interrupt(n)| n).collect()
+}
+
+/// What it takes to undo one instruction.
+pub struct Delta {
+    pc: u16,
+    lo: s16,
+    hi: s16,
+    /// Registers the instruction changed, with their old values.
+    regs: Vec<(Reg, s16)>,
+    /// Memory the instruction stored to: the address and the old bytes.
+    mem: Option<(u16, Vec<u8>)>,
+    interrupts_enabled: bool,
+    /// The pending interrupts, if the instruction took or raised one.
+    pending_interrupts: Option<Vec<u8>>,
+}
+
+/// The machine just before an instruction, to be turned into a `Delta` once
+/// it has executed.
+pub struct Before {
+    pc: u16,
+    lo: s16,
+    hi: s16,
+    /// In `Regs::iter` order.
+    regs: [u16; REG_COUNT],
+    mem: Option<(u16, Vec<u8>)>,
+    interrupts_enabled: bool,
+    /// Nearly always empty, so this doesn't allocate.
+    pending_interrupts: Vec<u8>,
+}
+
+impl Before {
+    /// `store` is the memory the instruction will write, if any.
+    pub fn record(cpu: &mut Cpu, store: Option<MemAccess>) -> Self {
+        let mem = store.map(|access| {
+            let old = (0..access.len)
+                .map(|i| cpu.mem.read_u8(access.addr.wrapping_add(i)))
+                .collect();
+            (access.addr, old)
+        });
+        let mut regs = [0; REG_COUNT];
+        for (old, (_, value)) in regs.iter_mut().zip(cpu.regs.iter()) {
+            *old = value.as_u16();
+        }
+        Self {
+            pc: cpu.pc,
+            lo: cpu.lo,
+            hi: cpu.hi,
+            regs,
+            mem,
+            interrupts_enabled: cpu.interrupts_enabled(),
+            pending_interrupts: pending_interrupts(cpu),
+        }
+    }
+
+    pub fn into_delta(self, cpu: &mut Cpu) -> Delta {
+        let regs = cpu
+            .regs
+            .iter()
+            .zip(self.regs)
+            .filter(|&((_, value), old)| value.as_u16() != old)
+            .map(|((reg, _), old)| (reg, old.into()))
+            .collect();
+        let pending_interrupts =
+            Some(self.pending_interrupts).filter(|pending| *pending != pending_interrupts(cpu));
+        Delta {
+            pc: self.pc,
+            lo: self.lo,
+            hi: self.hi,
+            regs,
+            mem: self.mem,
+            interrupts_enabled: self.interrupts_enabled,
+            pending_interrupts,
+        }
+    }
+}
+
+impl Delta {
+    /// Puts the machine back the way it was before the instruction.
+    pub fn undo(&self, cpu: &mut Cpu) {
+        cpu.pc = self.pc;
+        cpu.lo = self.lo;
+        cpu.hi = self.hi;
+        for &(reg, value) in &self.regs {
+            cpu.regs.set(reg, value);
+        }
+        if let Some((addr, old)) = &self.mem {
+            for (i, &b) in (0..).zip(old) {
+                cpu.mem.write_u8(addr.wrapping_add(i), b);
+            }
+        }
+        cpu.set_interrupts_enabled(self.interrupts_enabled);
+        if let Some(pending) = &self.pending_interrupts {
+            cpu.set_pending_interrupts(pending.iter().map(|&n| Interrupt(n)).collect());
+        }
+    }
+}
+
+/// The most recent `HISTORY_LEN` deltas, oldest first.
+#[derive(Default)]
+pub struct History {
+    deltas: VecDeque<Delta>,
+}
+
+impl History {
+    pub fn push(&mut self, delta: Delta) {
+        if self.deltas.len() == HISTORY_LEN {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    pub fn pop(&mut self) -> Option<Delta> {
+        self.deltas.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+    }
+
+    /// The address and length of the last instruction's store, if it made
+    /// one.
+    pub fn last_store(&self) -> Option<(u16, u16)> {
+        let (addr, old) = self.deltas.back()?.mem.as_ref()?;
+        Some((*addr, old.len() as u16))
+    }
+}
+
+impl Debugger {
+    /// Undoes the last recorded instruction, returning it if there was one.
+    fn undo_one(&mut self) -> Option<Delta> {
+        let delta = self.history.pop()?;
+        delta.undo(&mut self.cpu);
+        self.instrs_retired = self.instrs_retired.saturating_sub(1);
+        Some(delta)
+    }
+
+    pub(super) fn reverse_step(&mut self) {
+        self.stop_run();
+        match self.undo_one() {
+            Some(_) => self.cmd_log(format!("Stepped back to pc={}", self.describe_pc())),
+            None => self.cmd_err("No more recorded history.".to_string()),
+        }
+    }
+
+    /// Undoes instructions until one stored to a watched address or a
+    /// breakpoint is reached.
+    pub(super) fn reverse_continue(&mut self) {
+        self.stop_run();
+        self.cmd_log("Running backwards...".to_string());
+        while let Some(delta) = self.undo_one() {
+            if let Some((addr, old)) = &delta.mem {
+                let access = MemAccess {
+                    addr: *addr,
+                    len: old.len() as u16,
+                    kind: MemAccessKind::Write,
+                };
+                let watches = self.breakpoints.watches_hit(&access);
+                let ids = watches.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+                if let Some(id) = self.first_triggered(&ids) {
+                    let (_, watch) = watches.into_iter().find(|&(i, _)| i == id).unwrap();
+                    self.cmd_log(format!(
+                        "{} {id} at pc={}: 0x{:04X}:+{}",
+                        capitalize(&watch.on.to_string()),
+                        self.describe_pc(),
+                        watch.addr,
+                        watch.len,
+                    ));
+                    return;
+                }
+            }
+
+            let ids = self.breakpoints.code_at(self.cpu.pc);
+            if let Some(id) = self.first_triggered(&ids) {
+                self.cmd_log(format!("Breakpoint {id} at pc={}", self.describe_pc()));
+                return;
+            }
+        }
+        self.cmd_log(format!(
+            "Reached the start of recorded history at pc={}",
+            self.describe_pc()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::SourceFiles;
+
+    #[test]
+    fn undo() {
+        let mut dbg = Debugger::new(SourceFiles::default());
+        let cpu = &mut dbg.cpu;
+        let (a0, _) = cpu
+            .regs
+            .iter()
+            .find(|(r, _)| r.to_string() == "$a0")
+            .unwrap();
+        cpu.regs.set(a0, 5u16.into());
+        cpu.mem.write_u8(0x100, 0xAA);
+        let store = MemAccess {
+            addr: 0x100,
+            len: 2,
+            kind: MemAccessKind::Write,
+        };
+
+        let pc = cpu.pc;
+        let before = Before::record(cpu, Some(store));
+        cpu.pc = cpu.pc.wrapping_add(3);
+        cpu.lo = 7u16.into();
+        cpu.regs.set(a0, 6u16.into());
+        cpu.mem.write_u8(0x100, 1);
+        cpu.mem.write_u8(0x101, 2);
+        let delta = before.into_delta(cpu);
+        assert_eq!(delta.regs.len(), 1);
+        assert_eq!(delta.pending_interrupts, None);
+
+        delta.undo(cpu);
+        assert_eq!(cpu.pc, pc);
+        assert_eq!(cpu.lo.as_u16(), 0);
+        assert_eq!(cpu.regs.get(a0).as_u16(), 5);
+        assert_eq!(dbg.read_bytes(0x100, 2), [0xAA, 0]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Line tables, mapping ROM addresses back to the source lines they came
+//! from, for the source view and `step-line`/`next-line`.
+//!
+//! A line table lives next to the ROM (`prog.lines` for `prog.rom`) and has
+//! one `ADDR = FILE:LINE` per line, giving the line whose code starts at
+//! `ADDR`. The code for a line runs up to the next address in the table.
+//! `FILE` is relative to the table. Assembling `prog.lark` writes one, and so
+//! should a compiler whose output is to be debugged at the source level.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use super::commands::parse_number;
+
+/// A line of a source file: an index into `LineTable::files` and a 1-based
+/// line number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: usize,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    files: Vec<PathBuf>,
+    /// Sorted by address.
+    entries: Vec<(u16, SourceLine)>,
+}
+
+impl LineTable {
+    pub const EXTENSION: &'static str = "lines";
+
+    pub fn from_entries(entries: impl IntoIterator<Item = (u16, PathBuf, usize)>) -> Self {
+        let mut table = Self::default();
+        for (addr, path, line) in entries {
+            let file = match table.files.iter().position(|f| *f == path) {
+                Some(file) => file,
+                None => {
+                    table.files.push(path);
+                    table.files.len() - 1
+                }
+            };
+            table.entries.push((addr, SourceLine { file, line }));
+        }
+        table.entries.sort_by_key(|&(addr, _)| addr);
+        table
+    }
+
+    /// Parses a table, resolving file names relative to `dir`.
+    pub fn parse(src: &str, dir: &Path) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let parsed = line.split_once('=').and_then(|(addr, loc)| {
+                let (file, lineno) = loc.trim().rsplit_once(':')?;
+                let lineno = lineno.parse().ok().filter(|&n| n > 0)?;
+                Some((parse_number(addr.trim())?, dir.join(file), lineno))
+            });
+            match parsed {
+                Some(entry) => entries.push(entry),
+                None => return Err(format!("line {}: Expected `<ADDR> = <FILE>:<LINE>`", i + 1)),
+            }
+        }
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading `{}`: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&src, dir).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Writes the table to `path`, with file names relative to it where
+    /// possible.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut out = String::new();
+        for &(addr, loc) in &self.entries {
+            let file = &self.files[loc.file];
+            let file = file.strip_prefix(dir).unwrap_or(file);
+            writeln!(out, "0x{addr:04x} = {}:{}", file.display(), loc.line).unwrap();
+        }
+        std::fs::write(path, out).map_err(|e| format!("Error writing `{}`: {e}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The address of every line's code.
+    pub fn addrs(&self) -> impl Iterator<Item = u16> + '_ {
+        self.entries.iter().map(|&(addr, _)| addr)
+    }
+
+    pub fn path(&self, loc: SourceLine) -> &Path {
+        &self.files[loc.file]
+    }
+
+    /// The line whose code `addr` is part of.
+    pub fn line_at(&self, addr: u16) -> Option<SourceLine> {
+        let idx = self.entries.partition_point(|&(a, _)| a <= addr);
+        Some(self.entries.get(idx.checked_sub(1)?)?.1)
+    }
+
+    /// The line whose code starts at exactly `addr`.
+    pub fn line_starting_at(&self, addr: u16) -> Option<SourceLine> {
+        let idx = self.entries.partition_point(|&(a, _)| a < addr);
+        match self.entries.get(idx) {
+            Some(&(a, loc)) if a == addr => Some(loc),
+            _ => None,
+        }
+    }
+
+    /// `file:line`, for messages.
+    pub fn describe(&self, loc: SourceLine) -> String {
+        format!("{}:{}", self.path(loc).display(), loc.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let src =
+            "; prog.lines\n0x0806 = prog.lark:4\n0x0800 = prog.lark:2\n\n0x0810 = lib/io.lark:10\n";
+        let table = LineTable::parse(src, Path::new("dir")).unwrap();
+        assert_eq!(
+            table.files(),
+            [
+                PathBuf::from("dir/prog.lark"),
+                PathBuf::from("dir/lib/io.lark")
+            ]
+        );
+        assert_eq!(table.addrs().collect::<Vec<_>>(), [0x0800, 0x0806, 0x0810]);
+
+        for bad in [
+            "0x0800",
+            "0x0800 = prog.lark",
+            "0x0800 = prog.lark:0",
+            "nope = a:1",
+        ] {
+            assert_eq!(
+                LineTable::parse(bad, Path::new("")).err().as_deref(),
+                Some("line 1: Expected `<ADDR> = <FILE>:<LINE>`"),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let table = LineTable::from_entries([
+            (0x0800, PathBuf::from("a.lark"), 1),
+            (0x0804, PathBuf::from("a.lark"), 3),
+            (0x0810, PathBuf::from("b.lark"), 7),
+        ]);
+        let a3 = SourceLine { file: 0, line: 3 };
+        assert_eq!(table.line_at(0x07FF), None);
+        assert_eq!(table.line_at(0x0804), Some(a3));
+        assert_eq!(table.line_at(0x080F), Some(a3));
+        assert_eq!(table.line_at(0xFFFF), Some(SourceLine { file: 1, line: 7 }));
+        assert_eq!(table.line_starting_at(0x0804), Some(a3));
+        assert_eq!(table.line_starting_at(0x0805), None);
+        assert_eq!(table.describe(a3), "a.lark:3");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("lark-lines-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = LineTable::from_entries([
+            (0x0800, dir.join("prog.lark"), 1),
+            (0x0803, dir.join("inc/io.lark"), 12),
+        ]);
+        let path = dir.join("prog.lines");
+        table.save(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let loaded = LineTable::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Paths are written relative to the table.
+        assert_eq!(text, "0x0800 = prog.lark:1\n0x0803 = inc/io.lark:12\n");
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.files(), table.files());
+        assert_eq!(loaded.addrs().collect::<Vec<_>>(), [0x0800, 0x0803]);
+        assert_eq!(
+            loaded.line_at(0x0803),
+            Some(SourceLine { file: 1, line: 12 })
+        );
+    }
+}
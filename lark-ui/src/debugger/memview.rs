@@ -0,0 +1,155 @@
+//! The window on memory that the Memory tab shows, and `mem` to move it.
+//!
+//! Only the window is read and sent with each `MachineState`, rather than
+//! the whole address space. It's read through `MemRw` like everything else,
+//! except for the MMIO page, where reads have side effects. `mem follow
+//! <EXPR>` keeps the window on an address like `$sp` as it changes.
+
+use lark_vm::cpu::MemRw;
+
+use super::{expr::Expr, Debugger, MMIO_START};
+
+/// How many bytes the window holds.
+pub const MEM_VIEW_LEN: u16 = 0x200;
+
+/// How many bytes come before a followed address in the window, so what
+/// leads up to it is in view too.
+const FOLLOW_LEAD: u16 = 0x40;
+
+/// The part of memory the Memory tab shows.
+#[derive(Debug, Clone, Default)]
+pub struct MemWindow {
+    pub base: u16,
+    /// `None` where a read would have side effects.
+    pub bytes: Vec<Option<u8>>,
+    /// The address and length of the last instruction's store, if it made
+    /// one.
+    pub changed: Option<(u16, u16)>,
+    /// The `mem follow` expression, and its value if it could be evaluated.
+    pub follow: Option<(String, Option<u16>)>,
+}
+
+impl MemWindow {
+    pub fn contains(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.base) < self.bytes.len() as u16
+    }
+
+    pub fn get(&self, addr: u16) -> Option<u8> {
+        match self.contains(addr) {
+            true => self.bytes[addr.wrapping_sub(self.base) as usize],
+            false => None,
+        }
+    }
+
+    pub fn is_changed(&self, addr: u16) -> bool {
+        self.changed
+            .is_some_and(|(start, len)| addr.wrapping_sub(start) < len)
+    }
+}
+
+pub(super) struct Follow {
+    src: String,
+    expr: Expr,
+}
+
+#[derive(Default)]
+pub(super) struct MemView {
+    base: u16,
+    follow: Option<Follow>,
+}
+
+impl Debugger {
+    /// Moves the window to `base`, which stops following an expression.
+    pub fn view_mem(&mut self, base: u16) {
+        self.mem_view.base = base;
+        self.mem_view.follow = None;
+    }
+
+    /// Stores a byte or word at `addr`, for the Memory tab's editor and
+    /// `set`.
+    pub fn write_mem(&mut self, addr: u16, value: u16, word: bool) {
+        match word {
+            true => self.cpu.mem.write_s16(addr, value.into()),
+            false => self.cpu.mem.write_u8(addr, value as u8),
+        }
+        let value = match word {
+            true => format!("0x{value:04X}"),
+            false => format!("0x{value:02X}"),
+        };
+        self.cmd_info(format!("Wrote {value} to 0x{addr:04X}"));
+        self.note_rom_write(addr, 1 + word as u16);
+    }
+
+    /// `mem <EXPR>`
+    pub(super) fn mem_goto(&mut self, src: &str) {
+        let Some(addr) = self.eval_expr(src) else {
+            return;
+        };
+        self.view_mem(addr & !0xF);
+        self.cmd_info(format!("Memory view at 0x{addr:04X}"));
+    }
+
+    /// `mem follow <EXPR>`
+    pub(super) fn mem_follow(&mut self, src: &str) {
+        match Expr::parse(src, &self.cpu, &self.symbols) {
+            Ok(expr) => {
+                self.mem_view.follow = Some(Follow {
+                    src: src.to_owned(),
+                    expr,
+                });
+                self.cmd_info(format!("Memory view following `{src}`"));
+            }
+            Err(e) => self.cmd_err(e),
+        }
+    }
+
+    /// `mem follow off`
+    pub(super) fn mem_unfollow(&mut self) {
+        if self.mem_view.follow.take().is_some() {
+            self.cmd_info("Memory view no longer following.".to_string());
+        }
+    }
+
+    /// Parses the followed expression again, for the symbols of a reloaded
+    /// program.
+    pub(super) fn reparse_mem_follow(&mut self) {
+        let Some(follow) = &self.mem_view.follow else {
+            return;
+        };
+        match Expr::parse(&follow.src, &self.cpu, &self.symbols) {
+            Ok(expr) => self.mem_view.follow.as_mut().unwrap().expr = expr,
+            Err(e) => {
+                self.cmd_err(format!("No longer following `{}`: {e}", follow.src));
+                self.mem_view.follow = None;
+            }
+        }
+    }
+
+    pub(super) fn mem_window(&mut self) -> MemWindow {
+        let follow = self.mem_view.follow.as_ref().map(|follow| {
+            let value = follow.expr.eval(&mut self.cpu).ok();
+            (follow.src.clone(), value)
+        });
+        if let Some((_, Some(addr))) = follow {
+            self.mem_view.base = (addr & !0xF).saturating_sub(FOLLOW_LEAD);
+        }
+
+        let base = self.mem_view.base;
+        self.handle_signals();
+        let bytes = (0..MEM_VIEW_LEN)
+            .map(|i| base.checked_add(i))
+            .map(|addr| match addr {
+                Some(addr) if addr < MMIO_START => Some(self.cpu.mem.read_u8(addr)),
+                _ => None,
+            })
+            .collect();
+        self.discard_signals();
+
+        MemWindow {
+            base,
+            bytes,
+            changed: self.history.last_store(),
+            follow,
+        }
+    }
+}
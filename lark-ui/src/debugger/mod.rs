@@ -0,0 +1,718 @@
+//! The debugger core: owns the `Cpu` and carries out console commands against
+//! it. Whatever drives a `Debugger` (the TUI's worker thread, for one) collects
+//! what it has to say with `take_output` and renders `state` snapshots.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::mpsc::{Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use lark_vm::{
+    cpu::{self, interrupts::Interrupt, regs::Reg, Cpu, MemBlock, MemRw, Signal},
+    utils::s16,
+};
+
+use crate::{
+    build::{self, Lang},
+    diagnostic::Diagnostic,
+    disasm::{self, Item},
+    isa::{self, MemAccess, MemAccessKind},
+};
+
+use self::{
+    breakpoints::{BreakpointKind, Breakpoints},
+    commands::{capitalize, hex_bytes},
+    expr::Expr,
+    history::{Before, History},
+    memview::MemView,
+    reload::SrcWatch,
+    stepping::StepGoal,
+};
+
+pub use self::{
+    commands::parse_number,
+    lines::{LineTable, SourceLine},
+    memview::{MemWindow, MEM_VIEW_LEN},
+    reload::WATCH_INTERVAL,
+    stack::StackWord,
+    symbols::Symbols,
+};
+
+mod breakpoints;
+mod commands;
+mod expr;
+mod history;
+mod lines;
+mod memview;
+mod poke;
+mod reload;
+mod snapshot;
+mod stack;
+mod stepping;
+mod symbols;
+
+/// How long to run the CPU in one go before checking for new requests and
+/// publishing the machine state.
+const RUN_BATCH_TIME: Duration = Duration::from_millis(16);
+
+/// Reading the clock after every instruction is measurable, so batches only
+/// check it this often.
+const INSTRS_PER_CLOCK_CHECK: usize = 128;
+
+/// How long to count instructions for before updating the instr/sec figure.
+const IPS_WINDOW_TIME: Duration = Duration::from_millis(500);
+
+/// Reads from here up have side effects, so the debugger doesn't look.
+const MMIO_START: u16 = 0xF000;
+
+const KEY_CODE_ADDR: u16 = 0xF000;
+
+pub enum CmdMsg {
+    Log(String),
+    Info(String),
+    Error(String),
+    Command(String),
+    CpuMsg(cpu::LogMsg),
+}
+
+/// Why the CPU stopped a run of its own accord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Halt,
+    IllegalInstr,
+    /// The program executed a breakpoint instruction.
+    Breakpoint,
+    CpuError,
+}
+
+/// Something the debugger reports back to whoever is driving it.
+pub enum Output {
+    Msg(CmdMsg),
+    /// A ROM was loaded or written to, so the disassembly (and maybe the
+    /// source files and symbols) changed.
+    Loaded {
+        disassembly: Vec<Item>,
+        files: SourceFiles,
+        symbols: Symbols,
+        lines: LineTable,
+    },
+    /// `goto`: show this address in the disassembly.
+    Goto(u16),
+}
+
+/// The files a debugging session is working with.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFiles {
+    pub meadowlark_src: Option<PathBuf>,
+    pub lark_src: Option<PathBuf>,
+    pub romfile: Option<PathBuf>,
+}
+
+/// A copy of the parts of the machine the UI draws.
+pub struct MachineState {
+    pub regs: Vec<(Reg, s16)>,
+    pub lo: s16,
+    pub hi: s16,
+    pub pc: u16,
+    pub vtty: Vec<u8>,
+    pub running: bool,
+    pub instrs_retired: u64,
+    pub instrs_per_sec: Option<f64>,
+    /// The target instr/sec set with `speed`, or `None` to run flat out.
+    pub clock_hz: Option<u32>,
+    /// The address of each code breakpoint, and whether it's enabled.
+    pub breakpoints: Vec<(u16, bool)>,
+    pub memory: MemWindow,
+    /// The words around `$sp`.
+    pub stack: Vec<StackWord>,
+}
+
+impl MachineState {
+    /// The VTTY as text, one string per row.
+    pub fn vtty_rows(&self) -> Vec<String> {
+        vtty_rows(&self.vtty)
+    }
+}
+
+/// Interprets a VTTY buffer as rows of text `VTTY_COLS` bytes wide. Each row
+/// ends at its first 0 byte, and non-UTF-8 bytes are replaced.
+pub fn vtty_rows(vtty: &[u8]) -> Vec<String> {
+    vtty.chunks(cpu::VTTY_COLS)
+        .take(cpu::VTTY_ROWS)
+        .map(|row| {
+            let row_end = row.iter().position(|&b| b == 0).unwrap_or(row.len());
+            String::from_utf8_lossy(&row[..row_end]).into_owned()
+        })
+        .collect()
+}
+
+pub struct Debugger {
+    cpu: Cpu,
+    meadowlark_src: Option<PathBuf>,
+    lark_src: Option<PathBuf>,
+    romfile: Option<PathBuf>,
+    /// Labels for the loaded ROM, if it came with a symbol file.
+    symbols: Symbols,
+    /// Where each address's code came from, if the ROM came with a line table.
+    lines: LineTable,
+    vtty_buf: Rc<RefCell<MemBlock<{ cpu::VTTY_BYTES }>>>,
+
+    cpu_signal_channel: Receiver<Signal>,
+    cpu_interrupt_channel: Sender<Interrupt>,
+    cpu_run_till_breakpoint: bool,
+    /// Why the last run ended, if the CPU itself ended it.
+    stop_reason: Option<StopReason>,
+    breakpoints: Breakpoints,
+    /// Set when a run starts so that a breakpoint at the starting `pc` doesn't
+    /// immediately stop it again.
+    resume_pc: Option<u16>,
+    /// Where to stop a `next`, `finish` or `until` run.
+    step_goal: Option<StepGoal>,
+    /// Recent instructions, for running backwards.
+    history: History,
+    /// Errors from the last failed `load` of a source file.
+    diagnostics: Vec<Diagnostic>,
+    /// Set by `watch-src on`.
+    src_watch: Option<SrcWatch>,
+    /// What the Memory tab shows.
+    mem_view: MemView,
+
+    /// Instructions executed since the last reset.
+    instrs_retired: u64,
+    /// When the current instr/sec measurement started, and `instrs_retired`
+    /// at that time.
+    ips_window: Option<(Instant, u64)>,
+    instrs_per_sec: Option<f64>,
+    /// Instructions per second to pace runs at, or `None` to run flat out.
+    clock_hz: Option<u32>,
+    /// When the current paced stretch of a run started, and `instrs_retired`
+    /// at that time. Runs are paced against this rather than batch by batch
+    /// so that rounding doesn't add up.
+    pace_start: Option<(Instant, u64)>,
+
+    /// Waiting to be collected by `take_output`.
+    output: Vec<Output>,
+}
+
+impl Debugger {
+    pub fn new(files: SourceFiles) -> Self {
+        let vtty_buf = Rc::new(RefCell::new(MemBlock::new_zeroed()));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (interrupt_tx, interrupt_rx) = std::sync::mpsc::channel();
+
+        let cpu = Cpu::new(Default::default(), vtty_buf.clone(), tx, interrupt_rx);
+
+        let mut dbg = Self {
+            cpu,
+            meadowlark_src: files.meadowlark_src,
+            lark_src: files.lark_src,
+            romfile: files.romfile,
+            symbols: Symbols::default(),
+            lines: LineTable::default(),
+            vtty_buf,
+
+            cpu_signal_channel: rx,
+            cpu_interrupt_channel: interrupt_tx,
+            cpu_run_till_breakpoint: false,
+            stop_reason: None,
+            breakpoints: Breakpoints::default(),
+            resume_pc: None,
+            step_goal: None,
+            history: History::default(),
+            diagnostics: Vec::new(),
+            src_watch: None,
+            mem_view: MemView::default(),
+
+            instrs_retired: 0,
+            ips_window: None,
+            instrs_per_sec: None,
+            clock_hz: None,
+            pace_start: None,
+
+            output: Vec::new(),
+        };
+
+        if let Some(romfile) = dbg.romfile.clone() {
+            dbg.load_rom(&romfile);
+        }
+
+        dbg
+    }
+
+    pub fn take_output(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn state(&mut self) -> MachineState {
+        let memory = self.mem_window();
+        let stack = self.stack_words();
+        MachineState {
+            regs: self.cpu.regs.iter().collect(),
+            lo: self.cpu.lo,
+            hi: self.cpu.hi,
+            pc: self.cpu.pc,
+            vtty: self.vtty_buf.borrow().mem.to_vec(),
+            running: self.cpu_run_till_breakpoint,
+            instrs_retired: self.instrs_retired,
+            instrs_per_sec: self.instrs_per_sec,
+            clock_hz: self.clock_hz,
+            breakpoints: self
+                .breakpoints
+                .iter()
+                .filter_map(|(_, bp)| match bp.kind {
+                    BreakpointKind::Code(addr) => Some((addr, bp.enabled)),
+                    BreakpointKind::Watch(_) => None,
+                })
+                .collect(),
+            memory,
+            stack,
+        }
+    }
+
+    pub fn files(&self) -> SourceFiles {
+        SourceFiles {
+            meadowlark_src: self.meadowlark_src.clone(),
+            lark_src: self.lark_src.clone(),
+            romfile: self.romfile.clone(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.cpu_run_till_breakpoint
+    }
+
+    pub fn instrs_retired(&self) -> u64 {
+        self.instrs_retired
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub fn vtty_rows(&self) -> Vec<String> {
+        vtty_rows(&self.vtty_buf.borrow().mem[..])
+    }
+
+    /// Evaluates an expression like `print` does.
+    pub fn eval(&mut self, src: &str) -> Result<u16, String> {
+        Expr::parse(src, &self.cpu, &self.symbols).and_then(|e| e.eval(&mut self.cpu))
+    }
+
+    pub fn set_clock_hz(&mut self, clock_hz: Option<u32>) {
+        self.clock_hz = clock_hz;
+        self.pace_start = None;
+    }
+
+    /// How long until the next instruction of a paced run is due, if the run
+    /// is ahead of schedule.
+    pub fn time_until_next_instr(&self) -> Option<Duration> {
+        let hz = self.clock_hz?;
+        let (start, start_instrs) = self.pace_start?;
+        let next = self.instrs_retired - start_instrs + 1;
+        let due_at = Duration::from_secs_f64(next as f64 / hz as f64);
+        due_at.checked_sub(start.elapsed())
+    }
+
+    /// How many instructions a paced run has fallen behind by.
+    fn instrs_due(&mut self) -> u64 {
+        let Some(hz) = self.clock_hz else {
+            return u64::MAX;
+        };
+        let (start, start_instrs) = *self
+            .pace_start
+            .get_or_insert((Instant::now(), self.instrs_retired));
+        let due = (start.elapsed().as_secs_f64() * hz as f64) as u64;
+        (start_instrs + due).saturating_sub(self.instrs_retired)
+    }
+
+    /// Stops a run, as if Esc was pressed.
+    pub fn pause(&mut self) {
+        if self.cpu_run_till_breakpoint {
+            self.cmd_log("CPU halted.".to_string());
+            self.stop_run();
+        }
+    }
+
+    /// Delivers a key press to the program through the keyboard MMIO address
+    /// and interrupt.
+    pub fn key_event(&mut self, ch: u8) {
+        self.cpu.mem.write_u8(KEY_CODE_ADDR, ch);
+        self.cpu_interrupt_channel
+            .send(Interrupt::KEY_EVENT)
+            .expect("interrupt channel closed!");
+    }
+
+    fn cmd_log(&mut self, cmd: impl Into<String>) {
+        self.output.push(Output::Msg(CmdMsg::Log(cmd.into())));
+    }
+
+    fn cmd_info(&mut self, cmd: impl Into<String>) {
+        self.output.push(Output::Msg(CmdMsg::Info(cmd.into())));
+    }
+
+    fn cmd_err(&mut self, cmd: impl Into<String>) {
+        self.output.push(Output::Msg(CmdMsg::Error(cmd.into())));
+    }
+
+    /// Runs instructions until the run stops or `RUN_BATCH_TIME` has passed.
+    /// A paced run also stops once it has caught up with its clock rate.
+    pub fn run_batch(&mut self) {
+        let batch_start = Instant::now();
+        let (window_start, window_instrs) = *self
+            .ips_window
+            .get_or_insert((batch_start, self.instrs_retired));
+
+        'batch: while batch_start.elapsed() < RUN_BATCH_TIME {
+            let due = self.instrs_due().min(INSTRS_PER_CLOCK_CHECK as u64);
+            if due == 0 {
+                break;
+            }
+            for _ in 0..due {
+                self.run_one();
+                self.handle_signals();
+                if !self.cpu_run_till_breakpoint {
+                    break 'batch;
+                }
+            }
+        }
+
+        let window_time = window_start.elapsed();
+        if self.cpu_run_till_breakpoint && window_time >= IPS_WINDOW_TIME {
+            let instrs = self.instrs_retired - window_instrs;
+            self.instrs_per_sec = Some(instrs as f64 / window_time.as_secs_f64());
+            self.ips_window = Some((Instant::now(), self.instrs_retired));
+        }
+    }
+
+    /// Runs up to `n` instructions without looking at the clock, so that the
+    /// same program always stops in the same place.
+    pub fn run_instrs(&mut self, n: u64) {
+        for _ in 0..n {
+            self.run_one();
+            self.handle_signals();
+            if !self.cpu_run_till_breakpoint {
+                break;
+            }
+        }
+    }
+
+    /// Executes the next instruction of a run, unless a breakpoint stops it
+    /// first.
+    fn run_one(&mut self) {
+        let resuming = self.resume_pc.take() == Some(self.cpu.pc);
+        let ids = if resuming {
+            Vec::new()
+        } else {
+            self.breakpoints.code_at(self.cpu.pc)
+        };
+
+        if let Some(id) = self.first_triggered(&ids) {
+            self.cmd_log(format!("Breakpoint {id} at pc={}", self.describe_pc()));
+            self.stop_run();
+        } else if self.step_goal.is_some() {
+            let flow = self.pending_flow();
+            self.step_watched();
+            self.check_step_goal(flow);
+        } else {
+            self.step_watched();
+        }
+    }
+
+    pub fn stop_run(&mut self) {
+        self.cpu_run_till_breakpoint = false;
+        self.stop_reason = None;
+        self.step_goal = None;
+        self.ips_window = None;
+        self.instrs_per_sec = None;
+        self.pace_start = None;
+    }
+
+    pub fn handle_signals(&mut self) {
+        while let Ok(signal) = self.cpu_signal_channel.try_recv() {
+            match signal {
+                Signal::Log(msg) => {
+                    self.output.push(Output::Msg(CmdMsg::CpuMsg(msg)));
+                }
+                Signal::Halt => {
+                    self.cmd_log("CPU halted.".to_string());
+                    self.stop_run();
+                    self.stop_reason = Some(StopReason::Halt);
+                }
+                Signal::Breakpoint => {
+                    self.cmd_log(format!("BREAKPOINT at pc={}", self.describe_pc()));
+                    self.stop_run();
+                    self.stop_reason = Some(StopReason::Breakpoint);
+                }
+                Signal::IllegalInstr => {
+                    self.cmd_err(format!("Illegal instruction at pc={}", self.describe_pc()));
+                    self.stop_run();
+                    self.stop_reason = Some(StopReason::IllegalInstr);
+                }
+            }
+        }
+    }
+
+    /// Counts a hit on breakpoint `id` if its condition holds, and returns
+    /// whether the run should stop. A condition that fails to evaluate stops
+    /// the run so the error can be looked into.
+    fn breakpoint_triggers(&mut self, id: usize) -> bool {
+        let Some(bp) = self.breakpoints.get_mut(id) else {
+            return false;
+        };
+        match bp.condition.as_ref().map(|c| c.expr.eval(&mut self.cpu)) {
+            Some(Ok(0)) => false,
+            Some(Err(e)) => {
+                self.cmd_err(format!("Error in condition of breakpoint {id}: {e}"));
+                true
+            }
+            Some(Ok(_)) | None => bp.record_hit(),
+        }
+    }
+
+    /// Checks every breakpoint in `ids` so they all count the hit, returning
+    /// the first one that stops the run.
+    fn first_triggered(&mut self, ids: &[usize]) -> Option<usize> {
+        let mut first = None;
+        for &id in ids {
+            if self.breakpoint_triggers(id) {
+                first.get_or_insert(id);
+            }
+        }
+        first
+    }
+
+    /// Steps the CPU, recording it in the history and stopping the run if the
+    /// instruction touched memory under a watchpoint.
+    fn step_watched(&mut self) {
+        let pc = self.cpu.pc;
+        let access = self.pending_mem_access();
+        let hits = match access {
+            Some(access) if self.breakpoints.has_watchpoints() => {
+                self.breakpoints.watches_hit(&access)
+            }
+            _ => Vec::new(),
+        };
+        let olds = hits
+            .iter()
+            .map(|(_, w)| self.read_bytes(w.addr, w.len))
+            .collect::<Vec<_>>();
+
+        let store = access.filter(|a| a.kind == MemAccessKind::Write);
+        let before = Before::record(&mut self.cpu, store);
+        match self.cpu.step() {
+            Ok(_) => {
+                self.instrs_retired += 1;
+                self.history.push(before.into_delta(&mut self.cpu));
+            }
+            Err(e) => {
+                self.cmd_err(format!("CPU Error: {:?}", e));
+                self.stop_run();
+                self.stop_reason = Some(StopReason::CpuError);
+            }
+        }
+
+        for ((id, watch), old) in hits.into_iter().zip(olds) {
+            if !self.breakpoint_triggers(id) {
+                continue;
+            }
+            let new = self.read_bytes(watch.addr, watch.len);
+            self.cmd_log(format!(
+                "{} {id} at pc={}: 0x{:04X}:+{}",
+                capitalize(&watch.on.to_string()),
+                self.symbols.describe(pc),
+                watch.addr,
+                watch.len,
+            ));
+            self.cmd_log(format!("  Old value = {}", hex_bytes(&old)));
+            self.cmd_log(format!("  New value = {}", hex_bytes(&new)));
+            self.stop_run();
+        }
+    }
+
+    /// Decodes the load or store the instruction at `pc` is about to perform.
+    fn pending_mem_access(&mut self) -> Option<MemAccess> {
+        let pc = self.cpu.pc;
+        let bytes = [0, 1, 2].map(|i| self.cpu.mem.read_u8(pc.wrapping_add(i)));
+        let operand = isa::mem_operand(bytes)?;
+        Some(operand.resolve(self.reg_value(operand.base)))
+    }
+
+    fn reg_by_index(&self, idx: u8) -> Option<Reg> {
+        self.cpu
+            .regs
+            .iter()
+            .map(|(reg, _)| reg)
+            .find(|&reg| reg as u8 == idx)
+    }
+
+    fn reg_value(&self, idx: u8) -> u16 {
+        self.cpu
+            .regs
+            .iter()
+            .find(|(reg, _)| *reg as u8 == idx)
+            .map_or(0, |(_, value)| value.as_u16())
+    }
+
+    fn read_bytes(&mut self, addr: u16, len: u16) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.cpu.mem.read_u8(addr.wrapping_add(i)))
+            .collect()
+    }
+
+    /// Builds (if need be) and loads a program of any kind `build::Lang`
+    /// knows. Returns whether it loaded.
+    pub fn load(&mut self, path: &Path) -> bool {
+        match Lang::of(path) {
+            Some(Lang::Meadowlark) => self.load_meadowlark(path),
+            Some(Lang::Lark) => self.load_asm(path),
+            Some(Lang::Rom) => self.load_rom(path),
+            None => {
+                self.cmd_err(format!("Unknown file extension: {}", path.display()));
+                self.cmd_info(format!("  - Supported extensions: {}", Lang::EXTENSIONS));
+                false
+            }
+        }
+    }
+
+    /// Compiles `path` and loads the ROM. Returns whether it loaded.
+    pub fn load_meadowlark(&mut self, path: &Path) -> bool {
+        match build::compile_meadowlark(path, None) {
+            Ok(bin_path) => {
+                self.meadowlark_src = Some(path.to_path_buf());
+                self.load_rom(&bin_path)
+            }
+            Err(diags) => {
+                self.report_diagnostics(diags);
+                false
+            }
+        }
+    }
+
+    /// Assembles `path` into a `.bin` next to it, then loads that. Returns
+    /// whether it loaded.
+    pub fn load_asm(&mut self, path: &Path) -> bool {
+        match build::assemble(path, None) {
+            Ok(bin_path) => {
+                self.lark_src = Some(path.to_path_buf());
+                self.load_rom(&bin_path)
+            }
+            Err(diags) => {
+                self.report_diagnostics(diags);
+                false
+            }
+        }
+    }
+
+    /// Loads a ROM image, with its symbols and line table if it has them.
+    /// Returns whether it loaded.
+    pub fn load_rom(&mut self, path: &Path) -> bool {
+        let rom = match std::fs::read(path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                self.cmd_err(format!("Error reading ROM file: {}", e));
+                return false;
+            }
+        };
+
+        let romfile_size = rom.len();
+
+        let rom = match MemBlock::from_vec(rom) {
+            Some(rom) => rom,
+            None => {
+                self.cmd_err(format!("ROM file too large: {}", path.display()));
+                self.cmd_info(format!("  - Max ROM size: {}", lark_vm::cpu::ROM_SIZE));
+                self.cmd_info(format!("  - Given ROM size: {romfile_size}"));
+                return false;
+            }
+        };
+
+        self.reset_cpu();
+        self.diagnostics.clear();
+        self.romfile = Some(path.to_path_buf());
+        self.cpu.load_rom(rom);
+
+        let sym_path = path.with_extension(Symbols::EXTENSION);
+        self.symbols = match sym_path.exists() {
+            true => Symbols::load(&sym_path).unwrap_or_else(|e| {
+                self.cmd_err(e);
+                Symbols::default()
+            }),
+            false => Symbols::default(),
+        };
+        let lines_path = path.with_extension(LineTable::EXTENSION);
+        self.lines = match lines_path.exists() {
+            true => LineTable::load(&lines_path).unwrap_or_else(|e| {
+                self.cmd_err(e);
+                LineTable::default()
+            }),
+            false => LineTable::default(),
+        };
+        self.publish_loaded();
+
+        self.cmd_info(format!(
+            "Loaded ROM file `{}` ({romfile_size} bytes)",
+            self.romfile.as_ref().unwrap().display()
+        ));
+        if !self.symbols.is_empty() {
+            self.cmd_info(format!(
+                "Loaded {} symbols from `{}`",
+                self.symbols.len(),
+                sym_path.display()
+            ));
+        }
+        true
+    }
+
+    /// Tells the UI about a new or changed ROM, or a new symbol table.
+    fn publish_loaded(&mut self) {
+        let disassembly = self.disassembly();
+        self.output.push(Output::Loaded {
+            disassembly,
+            files: self.files(),
+            symbols: self.symbols.clone(),
+            lines: self.lines.clone(),
+        });
+    }
+
+    /// `pc` as `label+0xN` if there are symbols.
+    fn describe_pc(&self) -> String {
+        self.symbols.describe(self.cpu.pc)
+    }
+
+    /// Shows the first of `diags` in full and keeps them all for `errors`.
+    fn report_diagnostics(&mut self, diags: Vec<Diagnostic>) {
+        if let Some(first) = diags.first() {
+            self.cmd_err(first.render().join("\n"));
+        }
+        if diags.len() > 1 {
+            self.cmd_info(format!(
+                "{} more errors; use `errors` to list them all",
+                diags.len() - 1
+            ));
+        }
+        self.diagnostics = diags;
+    }
+
+    fn clear_vtty(&mut self) {
+        let mut vtty_buf = self.vtty_buf.borrow_mut();
+        vtty_buf.mem.fill(0);
+    }
+
+    fn reset_cpu(&mut self) {
+        self.cpu.reset();
+        self.instrs_retired = 0;
+        self.history.clear();
+        self.clear_vtty();
+    }
+
+    /// The ROM split into code and data, following control flow from the
+    /// start of the ROM and from every line in the line table.
+    fn disassembly(&self) -> Vec<Item> {
+        let rom: &[u8] = self.cpu.mem.rom.as_ref();
+        let entries = std::iter::once(cpu::Memory::ROM_START).chain(self.lines.addrs());
+        disasm::analyze(rom, entries, &self.symbols)
+    }
+}
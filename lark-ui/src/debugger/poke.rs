@@ -0,0 +1,291 @@
+//! `set`, `fill` and `copy`, which change registers and memory from the
+//! console.
+//!
+//! `set` takes anything `Expr` reads as a place: a register (`$a0`, `$pc`,
+//! `$lo`, `$hi`) or `[ADDR]`, which `set` stores a word to and `set.b` a
+//! byte. Memory is written through `MemRw` like the CPU would, so MMIO
+//! writes have their usual effect. Writes into ROM are allowed, since the
+//! program can't tell, but they're pointed out, as reloading undoes them,
+//! and the disassembly is redone.
+
+use lark_vm::cpu::{self, MemRw, Memory};
+
+use super::{expr::Expr, Debugger};
+
+/// The addresses the ROM is loaded at.
+const ROM: std::ops::Range<u32> =
+    Memory::ROM_START as u32..Memory::ROM_START as u32 + cpu::ROM_SIZE as u32;
+
+/// Whether `value` is a byte, signed or not.
+fn fits_byte(value: u16) -> bool {
+    value <= 0xFF || (-0x80..0).contains(&(value as i16))
+}
+
+/// Splits `set`'s `PLACE = VALUE` at the `=`, which mustn't be part of `==`,
+/// `!=`, `<=` or `>=`.
+fn split_assignment(src: &str) -> Option<(&str, &str)> {
+    let bytes = src.as_bytes();
+    let i = (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && bytes.get(i + 1) != Some(&b'=')
+            && !matches!(
+                i.checked_sub(1).map(|j| bytes[j]),
+                Some(b'=' | b'!' | b'<' | b'>')
+            )
+    })?;
+    Some((src[..i].trim(), src[i + 1..].trim()))
+}
+
+impl Debugger {
+    /// `set PLACE = EXPR` and `set.b [ADDR] = EXPR`
+    pub(super) fn set(&mut self, src: &str, byte: bool) {
+        let Some((place, value_src)) = split_assignment(src) else {
+            self.cmd_err("Expected `set <PLACE> = <EXPR>`".to_string());
+            return;
+        };
+        let place_expr = match Expr::parse(place, &self.cpu, &self.symbols) {
+            Ok(expr) => expr,
+            Err(e) => {
+                self.cmd_err(e);
+                return;
+            }
+        };
+        let Some(value) = self.eval_expr(value_src) else {
+            return;
+        };
+
+        match place_expr {
+            Expr::Deref(addr) => {
+                let addr = match addr.eval(&mut self.cpu) {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        self.cmd_err(e);
+                        return;
+                    }
+                };
+                if byte && !fits_byte(value) {
+                    self.cmd_err(format!("{value_src} = 0x{value:04X}, which isn't a byte"));
+                    return;
+                }
+                self.write_mem(addr, value, !byte);
+            }
+            _ if byte => {
+                self.cmd_err(format!("`set.b` only stores to memory, not `{place}`"));
+            }
+            Expr::Reg(reg) if reg as u8 == 0 => {
+                self.cmd_err(format!("`{reg}` is always zero and can't be set"));
+            }
+            Expr::Reg(reg) => {
+                self.cpu.regs.set(reg, value.into());
+                self.cmd_info(format!("{reg} = 0x{value:04X} ({value})"));
+            }
+            Expr::Lo => {
+                self.cpu.lo = value.into();
+                self.cmd_info(format!("$lo = 0x{value:04X} ({value})"));
+            }
+            Expr::Hi => {
+                self.cpu.hi = value.into();
+                self.cmd_info(format!("$hi = 0x{value:04X} ({value})"));
+            }
+            Expr::Pc => {
+                self.cpu.pc = value;
+                self.cmd_info(format!("$pc = {}", self.symbols.describe(value)));
+            }
+            _ => {
+                self.cmd_err(format!(
+                    "Can't set `{place}`: expected a register or `[<ADDR>]`"
+                ));
+            }
+        }
+    }
+
+    /// `fill ADDR :+ LEN BYTE`
+    pub(super) fn fill(&mut self, addr_src: &str, len_src: &str, byte_src: &str) {
+        let (Some(addr), Some(len), Some(byte)) = (
+            self.eval_expr(addr_src),
+            self.eval_expr(len_src),
+            self.eval_expr(byte_src),
+        ) else {
+            return;
+        };
+        if !fits_byte(byte) {
+            self.cmd_err(format!("{byte_src} = 0x{byte:04X}, which isn't a byte"));
+            return;
+        }
+        if self.check_range(addr, len) {
+            self.store(addr, &vec![byte as u8; len as usize]);
+        }
+    }
+
+    /// `copy SRC DST LEN`. The ranges may overlap.
+    pub(super) fn copy(&mut self, src_src: &str, dst_src: &str, len_src: &str) {
+        let (Some(src), Some(dst), Some(len)) = (
+            self.eval_expr(src_src),
+            self.eval_expr(dst_src),
+            self.eval_expr(len_src),
+        ) else {
+            return;
+        };
+        if !self.check_range(src, len) || !self.check_range(dst, len) {
+            return;
+        }
+        if src as u32 + len as u32 > super::MMIO_START as u32 {
+            self.cmd_err(
+                "Won't copy from the MMIO page, where reads have side effects".to_string(),
+            );
+            return;
+        }
+        self.handle_signals();
+        let bytes = self.read_bytes(src, len);
+        self.discard_signals();
+        self.store(dst, &bytes);
+    }
+
+    /// Reports an error if `addr :+ len` is empty or runs off the end of
+    /// memory.
+    fn check_range(&mut self, addr: u16, len: u16) -> bool {
+        if len == 0 {
+            self.cmd_err("Length must be more than zero".to_string());
+            false
+        } else if addr.checked_add(len - 1).is_none() {
+            self.cmd_err(format!("0x{addr:04X} :+ {len} runs past 0xFFFF"));
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Writes `bytes` from `addr` up and says how many were written.
+    fn store(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, &b) in bytes.iter().enumerate() {
+            self.cpu.mem.write_u8(addr.wrapping_add(i as u16), b);
+        }
+        self.cmd_info(format!("Wrote {} bytes to 0x{addr:04X}", bytes.len()));
+        self.note_rom_write(addr, bytes.len() as u16);
+    }
+
+    /// Points out a write to `addr :+ len` if it touched ROM, and sends the
+    /// disassembly again as the code there has changed.
+    pub(super) fn note_rom_write(&mut self, addr: u16, len: u16) {
+        let end = addr as u32 + len as u32;
+        if (addr as u32) < ROM.end && end > ROM.start {
+            self.cmd_info("  - That's in ROM; reloading the program will undo it".to_string());
+            self.publish_loaded();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::{CmdMsg, Output, SourceFiles};
+
+    #[test]
+    fn assignment() {
+        assert_eq!(split_assignment("$a0 = 5"), Some(("$a0", "5")));
+        assert_eq!(split_assignment("[$sp]=$a0"), Some(("[$sp]", "$a0")));
+        assert_eq!(
+            split_assignment("[$sp + 2] = $a0 == 1"),
+            Some(("[$sp + 2]", "$a0 == 1"))
+        );
+        assert_eq!(split_assignment("$a0 <= 1 = 2"), Some(("$a0 <= 1", "2")));
+        for no_assignment in ["$a0", "$a0 == 1", "$a0 != 1", "1 <= 2", "1 >= 2"] {
+            assert_eq!(split_assignment(no_assignment), None, "{no_assignment}");
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        for byte in [0, 0x7F, 0xFF, -1i16 as u16, -0x80i16 as u16] {
+            assert!(fits_byte(byte), "{byte:#06x}");
+        }
+        for not_byte in [0x100, 0x7FFF, -0x81i16 as u16] {
+            assert!(!fits_byte(not_byte), "{not_byte:#06x}");
+        }
+    }
+
+    fn errors(dbg: &mut Debugger) -> Vec<String> {
+        dbg.take_output()
+            .into_iter()
+            .filter_map(|out| match out {
+                Output::Msg(CmdMsg::Error(e)) => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn set() {
+        let mut dbg = Debugger::new(SourceFiles::default());
+        dbg.set("$a0 = 3 + 4", false);
+        dbg.set("$lo = -1", false);
+        dbg.set("[0x100] = 0x1234", false);
+        dbg.set(r"[0x102] = '\n'", true);
+        assert_eq!(errors(&mut dbg), Vec::<String>::new());
+        assert_eq!(dbg.eval("$a0"), Ok(7));
+        assert_eq!(dbg.eval("$lo"), Ok(0xFFFF));
+        assert_eq!(dbg.eval("[0x100]"), Ok(0x1234));
+        assert_eq!(dbg.read_bytes(0x102, 1), [b'\n']);
+
+        dbg.set("$zero = 1", false);
+        dbg.set("$a0 = 1", true);
+        dbg.set("[0x100] = 0x100", true);
+        dbg.set("5 = 1", false);
+        dbg.set("$a0", false);
+        assert_eq!(
+            errors(&mut dbg),
+            [
+                "`$zero` is always zero and can't be set",
+                "`set.b` only stores to memory, not `$a0`",
+                "0x100 = 0x0100, which isn't a byte",
+                "Can't set `5`: expected a register or `[<ADDR>]`",
+                "Expected `set <PLACE> = <EXPR>`",
+            ]
+        );
+        assert_eq!(dbg.eval("$a0"), Ok(7));
+    }
+
+    #[test]
+    fn fill_and_copy() {
+        let mut dbg = Debugger::new(SourceFiles::default());
+        dbg.fill("0x200", "4", "0xAA");
+        dbg.fill("0x202", "2", "-1");
+        assert_eq!(dbg.read_bytes(0x200, 5), [0xAA, 0xAA, 0xFF, 0xFF, 0]);
+
+        // Overlapping ranges copy as if through a buffer.
+        dbg.store(0x300, &[1, 2, 3, 4]);
+        dbg.copy("0x300", "0x302", "4");
+        assert_eq!(dbg.read_bytes(0x300, 6), [1, 2, 1, 2, 3, 4]);
+        assert_eq!(errors(&mut dbg), Vec::<String>::new());
+
+        dbg.fill("0x200", "0", "1");
+        dbg.fill("0xFFFE", "3", "1");
+        dbg.fill("0x200", "1", "0x100");
+        dbg.copy("0xEFFF", "0x200", "2");
+        assert_eq!(
+            errors(&mut dbg),
+            [
+                "Length must be more than zero",
+                "0xFFFE :+ 3 runs past 0xFFFF",
+                "0x100 = 0x0100, which isn't a byte",
+                "Won't copy from the MMIO page, where reads have side effects",
+            ]
+        );
+    }
+
+    #[test]
+    fn rom_writes_redo_the_disassembly() {
+        let mut dbg = Debugger::new(SourceFiles::default());
+        let loaded = |dbg: &mut Debugger| {
+            dbg.take_output()
+                .iter()
+                .any(|out| matches!(out, Output::Loaded { .. }))
+        };
+        dbg.set("[0x100] = 1", false);
+        assert!(!loaded(&mut dbg));
+        dbg.set(&format!("[{}] = 0x0400", Memory::ROM_START), false);
+        assert!(loaded(&mut dbg));
+        dbg.fill(&format!("{}", Memory::ROM_START - 1), "2", "0");
+        assert!(loaded(&mut dbg));
+    }
+}
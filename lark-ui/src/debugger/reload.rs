@@ -0,0 +1,254 @@
+//! `watch-src on|off`: rebuilds and reloads the program whenever its source
+//! changes, so editing doesn't mean restarting the debugger.
+//!
+//! Files are polled for a new modification time rather than watched, which
+//! is cheap at this rate and needs no platform support. After a reload,
+//! breakpoints set at a label (or `label+0xN`) follow the label to its new
+//! address, and conditions are parsed again so they see the new symbols.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{
+    breakpoints::{BreakpointKind, Condition},
+    expr::Expr,
+    Debugger, Symbols,
+};
+
+/// How often to check the watched files.
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub(super) struct SrcWatch {
+    /// Each watched file and its modification time when last checked.
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+/// What a reload rebuilds.
+enum Build {
+    Meadowlark(PathBuf),
+    Lark(PathBuf),
+    Rom(PathBuf),
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Debugger {
+    pub fn watching_src(&self) -> bool {
+        self.src_watch.is_some()
+    }
+
+    pub(super) fn set_watch_src(&mut self, on: bool) {
+        if !on {
+            self.src_watch = None;
+            self.cmd_info("No longer watching source files.".to_string());
+            return;
+        }
+        let files = self.watched_files();
+        if files.is_empty() {
+            self.cmd_err("Nothing is loaded, so there's nothing to watch.".to_string());
+            return;
+        }
+        let names = files
+            .iter()
+            .map(|path| format!("`{}`", path.display()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.cmd_info(format!("Watching {names} for changes."));
+        self.src_watch = Some(SrcWatch {
+            files: files
+                .into_iter()
+                .map(|path| {
+                    let t = mtime(&path);
+                    (path, t)
+                })
+                .collect(),
+            last_check: Instant::now(),
+        });
+    }
+
+    /// The source the loaded ROM was built from, if it's known.
+    fn build(&self) -> Option<Build> {
+        let romfile = self.romfile.as_ref();
+        if let Some(src) = &self.lark_src {
+            if romfile == Some(&src.with_extension("bin")) {
+                return Some(Build::Lark(src.clone()));
+            }
+        }
+        if let Some(src) = &self.meadowlark_src {
+            return Some(Build::Meadowlark(src.clone()));
+        }
+        romfile.cloned().map(Build::Rom)
+    }
+
+    /// The source file plus any others the line table mentions (such as
+    /// `#include`s), or just the romfile if there's no source.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files = match self.build() {
+            Some(Build::Meadowlark(path) | Build::Lark(path) | Build::Rom(path)) => vec![path],
+            None => return Vec::new(),
+        };
+        for path in self.lines.files() {
+            if !files.contains(path) {
+                files.push(path.clone());
+            }
+        }
+        files
+    }
+
+    /// Rebuilds and reloads if a watched file has changed since the last
+    /// check. Cheap to call often.
+    pub fn poll_src(&mut self) {
+        let Some(watch) = &mut self.src_watch else {
+            return;
+        };
+        if watch.last_check.elapsed() < WATCH_INTERVAL {
+            return;
+        }
+        watch.last_check = Instant::now();
+
+        let mut changed = Vec::new();
+        for (path, last) in &mut watch.files {
+            let now = mtime(path);
+            if now != *last {
+                *last = now;
+                changed.push(path.clone());
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        for path in &changed {
+            self.cmd_log(format!("`{}` changed, reloading...", path.display()));
+        }
+        self.reload();
+    }
+
+    fn reload(&mut self) {
+        let Some(build) = self.build() else {
+            return;
+        };
+        self.stop_run();
+
+        let old_symbols = self.symbols.clone();
+        let old_rom_len = self.rom_len();
+        let loaded = match &build {
+            Build::Meadowlark(path) => self.load_meadowlark(path),
+            Build::Lark(path) => self.load_asm(path),
+            Build::Rom(path) => self.load_rom(path),
+        };
+        if !loaded {
+            self.cmd_err("Reload failed; the previous program is still loaded.".to_string());
+            return;
+        }
+
+        // A build can pull in new files.
+        let files = self.watched_files();
+        if let Some(watch) = &mut self.src_watch {
+            for path in files {
+                if !watch.files.iter().any(|(p, _)| *p == path) {
+                    let t = mtime(&path);
+                    watch.files.push((path, t));
+                }
+            }
+        }
+
+        self.cmd_info(format!(
+            "ROM size: {old_rom_len} -> {} bytes",
+            self.rom_len()
+        ));
+        self.report_label_changes(&old_symbols);
+        self.move_breakpoints(&old_symbols);
+        self.reparse_mem_follow();
+    }
+
+    /// Bytes up to the last nonzero one, which is as close as a ROM image
+    /// gets to a length once it's loaded.
+    fn rom_len(&self) -> usize {
+        let rom = &self.cpu.mem.rom.mem;
+        rom.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1)
+    }
+
+    fn report_label_changes(&mut self, old: &Symbols) {
+        let (mut added, mut removed, mut moved) = (0, 0, 0);
+        for (addr, name) in self.symbols.iter() {
+            match old.lookup(name) {
+                None => added += 1,
+                Some(old_addr) if old_addr != addr => moved += 1,
+                Some(_) => {}
+            }
+        }
+        for (_, name) in old.iter() {
+            if self.symbols.lookup(name).is_none() {
+                removed += 1;
+            }
+        }
+        if added + removed + moved > 0 {
+            self.cmd_info(format!(
+                "Labels: {added} added, {removed} removed, {moved} moved"
+            ));
+        }
+    }
+
+    /// Moves each breakpoint to wherever the label it was at went, and
+    /// disables the ones whose label is gone.
+    fn move_breakpoints(&mut self, old: &Symbols) {
+        let ids = self
+            .breakpoints
+            .iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            let Some(bp) = self.breakpoints.get_mut(id) else {
+                continue;
+            };
+            let addr = match &mut bp.kind {
+                BreakpointKind::Code(addr) => addr,
+                BreakpointKind::Watch(w) => &mut w.addr,
+            };
+            let old_addr = *addr;
+            let mut msg = None;
+            if let Some((name, offset)) = old.locate(old_addr) {
+                match self.symbols.lookup(name) {
+                    Some(base) => {
+                        *addr = base.wrapping_add(offset);
+                        if *addr != old_addr {
+                            msg = Some(format!(
+                                "Breakpoint {id} moved from 0x{old_addr:04X} to {}",
+                                self.symbols.annotate(*addr)
+                            ));
+                        }
+                    }
+                    None => {
+                        bp.enabled = false;
+                        msg = Some(format!(
+                            "Breakpoint {id} disabled: `{name}` no longer exists"
+                        ));
+                    }
+                }
+            }
+
+            if let Some(cond) = &bp.condition {
+                match Expr::parse(&cond.src, &self.cpu, &self.symbols) {
+                    Ok(expr) => {
+                        let src = cond.src.clone();
+                        bp.condition = Some(Condition { src, expr });
+                    }
+                    Err(e) => {
+                        bp.enabled = false;
+                        msg = Some(format!("Breakpoint {id} disabled: {e}"));
+                    }
+                }
+            }
+
+            if let Some(msg) = msg {
+                self.cmd_info(msg);
+            }
+        }
+    }
+}
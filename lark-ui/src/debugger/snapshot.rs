@@ -0,0 +1,365 @@
+//! `snapshot save/load/list`: the whole machine written to a file next to the
+//! romfile, so a state can be shared instead of the steps to reach it.
+//!
+//! Everything is read through `Cpu`'s public interface: the registers, `pc`,
+//! `lo`/`hi`, whether interrupts are enabled and which are pending, the ROM,
+//! memory below the MMIO page (reads there have side effects) and the VTTY
+//! buffer.
+//!
+//! The file is little-endian:
+//!
+//! ```text
+//! "LARKSNAP" version:u16
+//! pc:u16 lo:u16 hi:u16 instrs_retired:u64
+//! reg_count:u8 (reg_index:u8 value:u16)*
+//! interrupts_enabled:u8 pending_count:u8 (interrupt:u8)*
+//! rom_len:u32 rom
+//! mem_len:u32 mem
+//! vtty_len:u32 vtty
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use lark_vm::cpu::{self, interrupts::Interrupt, MemRw, Memory};
+
+use super::{Debugger, MMIO_START};
+
+const MAGIC: &[u8; 8] = b"LARKSNAP";
+/// Version 1 didn't have the interrupt state.
+const VERSION: u16 = 2;
+const EXTENSION: &str = "larksnap";
+
+struct Snapshot {
+    pc: u16,
+    lo: u16,
+    hi: u16,
+    instrs_retired: u64,
+    regs: Vec<(u8, u16)>,
+    interrupts_enabled: bool,
+    /// Interrupts raised but not yet taken, oldest first.
+    pending_interrupts: Vec<u8>,
+    rom: Vec<u8>,
+    mem: Vec<u8>,
+    vtty: Vec<u8>,
+}
+
+impl Snapshot {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        for x in [self.pc, self.lo, self.hi] {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        out.extend_from_slice(&self.instrs_retired.to_le_bytes());
+        out.push(self.regs.len() as u8);
+        for &(idx, value) in &self.regs {
+            out.push(idx);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.push(self.interrupts_enabled as u8);
+        out.push(self.pending_interrupts.len() as u8);
+        out.extend_from_slice(&self.pending_interrupts);
+        for block in [&self.rom, &self.mem, &self.vtty] {
+            out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader { bytes };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err("Not a snapshot file".to_string());
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported snapshot version {version} (expected {VERSION})"
+            ));
+        }
+        let pc = r.u16()?;
+        let lo = r.u16()?;
+        let hi = r.u16()?;
+        let instrs_retired = u64::from_le_bytes(r.take(8)?.try_into().unwrap());
+        let reg_count = r.take(1)?[0];
+        let regs = (0..reg_count)
+            .map(|_| Ok((r.take(1)?[0], r.u16()?)))
+            .collect::<Result<_, String>>()?;
+        let interrupts_enabled = r.take(1)?[0] != 0;
+        let pending_count = r.take(1)?[0];
+        let pending_interrupts = r.take(pending_count as usize)?.to_vec();
+        let rom = r.block()?;
+        let mem = r.block()?;
+        let vtty = r.block()?;
+        if !r.bytes.is_empty() {
+            return Err("Trailing bytes after snapshot".to_string());
+        }
+        Ok(Self {
+            pc,
+            lo,
+            hi,
+            instrs_retired,
+            regs,
+            interrupts_enabled,
+            pending_interrupts,
+            rom,
+            mem,
+            vtty,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("Snapshot file is truncated".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn block(&mut self) -> Result<Vec<u8>, String> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        Ok(self.take(len as usize)?.to_vec())
+    }
+}
+
+fn in_rom(addr: u16) -> bool {
+    (Memory::ROM_START as usize..Memory::ROM_START as usize + cpu::ROM_SIZE)
+        .contains(&(addr as usize))
+}
+
+impl Debugger {
+    /// Where snapshot `name` of the current romfile lives: `prog.rom` keeps
+    /// its snapshots in `prog.<NAME>.larksnap` beside it.
+    fn snapshot_path(&mut self, name: &str) -> Option<PathBuf> {
+        let Some(romfile) = &self.romfile else {
+            self.cmd_err("No ROM is loaded, so there's nowhere to keep snapshots.".to_string());
+            return None;
+        };
+        let stem = romfile.file_stem().unwrap_or_default().to_string_lossy();
+        Some(romfile.with_file_name(format!("{stem}.{name}.{EXTENSION}")))
+    }
+
+    fn snapshot(&mut self) -> Snapshot {
+        self.handle_signals();
+        let mem = (0..MMIO_START).map(|a| self.cpu.mem.read_u8(a)).collect();
+        self.discard_signals();
+
+        Snapshot {
+            pc: self.cpu.pc,
+            lo: self.cpu.lo.as_u16(),
+            hi: self.cpu.hi.as_u16(),
+            instrs_retired: self.instrs_retired,
+            regs: self
+                .cpu
+                .regs
+                .iter()
+                .map(|(reg, value)| (reg as u8, value.as_u16()))
+                .collect(),
+            interrupts_enabled: self.cpu.interrupts_enabled(),
+            pending_interrupts: self
+                .cpu
+                .pending_interrupts()
+                .into_iter()
+                .map(|Interrupt(n)| n)
+                .collect(),
+            rom: self.cpu.mem.rom.mem.to_vec(),
+            mem,
+            vtty: self.vtty_buf.borrow().mem.to_vec(),
+        }
+    }
+
+    /// Drops the signals from the debugger touching memory itself, which
+    /// aren't the program's doing. Call `handle_signals` first so none of
+    /// the program's are lost.
+    pub(super) fn discard_signals(&mut self) {
+        while self.cpu_signal_channel.try_recv().is_ok() {}
+    }
+
+    fn restore(&mut self, snap: Snapshot) {
+        self.handle_signals();
+        self.stop_run();
+        self.history.clear();
+
+        self.cpu.pc = snap.pc;
+        self.cpu.lo = snap.lo.into();
+        self.cpu.hi = snap.hi.into();
+        self.instrs_retired = snap.instrs_retired;
+        for (idx, value) in snap.regs {
+            if let Some(reg) = self.reg_by_index(idx) {
+                self.cpu.regs.set(reg, value.into());
+            }
+        }
+        self.cpu.set_interrupts_enabled(snap.interrupts_enabled);
+        self.cpu
+            .set_pending_interrupts(snap.pending_interrupts.into_iter().map(Interrupt).collect());
+
+        let rom = &mut self.cpu.mem.rom.mem;
+        let n = rom.len().min(snap.rom.len());
+        rom[..n].copy_from_slice(&snap.rom[..n]);
+        for (addr, b) in (0..MMIO_START).zip(snap.mem) {
+            if !in_rom(addr) {
+                self.cpu.mem.write_u8(addr, b);
+            }
+        }
+        let mut vtty_buf = self.vtty_buf.borrow_mut();
+        let n = vtty_buf.mem.len().min(snap.vtty.len());
+        vtty_buf.mem[..n].copy_from_slice(&snap.vtty[..n]);
+        drop(vtty_buf);
+
+        self.discard_signals();
+
+        self.publish_loaded();
+    }
+
+    pub(super) fn snapshot_save(&mut self, name: &str) {
+        let Some(path) = self.snapshot_path(name) else {
+            return;
+        };
+        let bytes = self.snapshot().encode();
+        match std::fs::write(&path, bytes) {
+            Ok(()) => self.cmd_info(format!("Saved snapshot to `{}`", path.display())),
+            Err(e) => self.cmd_err(format!("Error writing snapshot: {e}")),
+        }
+    }
+
+    pub(super) fn snapshot_load(&mut self, name: &str) {
+        let Some(path) = self.snapshot_path(name) else {
+            return;
+        };
+        let snap = match std::fs::read(&path) {
+            Ok(bytes) => Snapshot::decode(&bytes),
+            Err(e) => Err(format!("Error reading `{}`: {e}", path.display())),
+        };
+        match snap {
+            Ok(snap) => {
+                self.restore(snap);
+                self.cmd_info(format!(
+                    "Loaded snapshot `{name}` at pc={}",
+                    self.describe_pc()
+                ));
+            }
+            Err(e) => self.cmd_err(e),
+        }
+    }
+
+    pub(super) fn snapshot_list(&mut self) {
+        let Some(romfile) = self.romfile.clone() else {
+            self.cmd_err("No ROM is loaded, so there are no snapshots.".to_string());
+            return;
+        };
+        let stem = romfile.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = romfile.parent().unwrap_or(Path::new("."));
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let prefix = format!("{stem}.");
+        let suffix = format!(".{EXTENSION}");
+
+        let mut names = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                let name = file_name.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+                Some(name.to_owned())
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+
+        if names.is_empty() {
+            self.cmd_info("No snapshots.".to_string());
+        }
+        for name in names {
+            self.cmd_info(format!("  - {name}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Snapshot {
+        Snapshot {
+            pc: 0x0804,
+            lo: 0x1234,
+            hi: 0xFFFF,
+            instrs_retired: 1 << 40,
+            regs: vec![(1, 15), (15, 0xEFFE)],
+            interrupts_enabled: true,
+            pending_interrupts: vec![1, 1],
+            rom: vec![0x40, 0xC0, 0x01, 0x40],
+            mem: vec![0; 16],
+            vtty: b"hi".to_vec(),
+        }
+    }
+
+    fn decode_err(bytes: &[u8]) -> String {
+        match Snapshot::decode(bytes) {
+            Ok(_) => panic!("decoded"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = sample().encode();
+        let snap = Snapshot::decode(&bytes).unwrap();
+        assert_eq!(snap.pc, 0x0804);
+        assert_eq!((snap.lo, snap.hi), (0x1234, 0xFFFF));
+        assert_eq!(snap.instrs_retired, 1 << 40);
+        assert_eq!(snap.regs, [(1, 15), (15, 0xEFFE)]);
+        assert!(snap.interrupts_enabled);
+        assert_eq!(snap.pending_interrupts, [1, 1]);
+        assert_eq!(snap.rom, [0x40, 0xC0, 0x01, 0x40]);
+        assert_eq!(snap.mem, [0; 16]);
+        assert_eq!(snap.vtty, b"hi");
+        assert_eq!(snap.encode(), bytes);
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = sample().encode();
+        assert_eq!(&bytes[..8], b"LARKSNAP");
+        assert_eq!(bytes[8..10], VERSION.to_le_bytes());
+        // pc, lo, hi
+        assert_eq!(bytes[10..16], [0x04, 0x08, 0x34, 0x12, 0xFF, 0xFF]);
+        // The registers, then the interrupt state.
+        assert_eq!(bytes[24..31], [2, 1, 15, 0, 15, 0xFE, 0xEF]);
+        assert_eq!(bytes[31..35], [1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn bad_files() {
+        let bytes = sample().encode();
+        assert_eq!(decode_err(b"NOTASNAPSHOT"), "Not a snapshot file");
+        let mut old = bytes.clone();
+        old[8..10].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(
+            decode_err(&old),
+            "Unsupported snapshot version 1 (expected 2)"
+        );
+        assert_eq!(
+            decode_err(&bytes[..bytes.len() - 1]),
+            "Snapshot file is truncated"
+        );
+        let mut long = bytes;
+        long.push(0);
+        assert_eq!(decode_err(&long), "Trailing bytes after snapshot");
+    }
+}
@@ -0,0 +1,201 @@
+//! Working out the call stack, for `backtrace` and the Stack panel.
+//!
+//! Lark frames don't link to each other, so each one is pieced together from
+//! its function's prologue: from the label before `pc` up to `pc` (or the
+//! first jump, branch or call), every `addi`/`subi` on `$sp` and every `sw`
+//! of `$ra` or a saved register (`Reg::is_saved`) through `$sp` is noted.
+//! That gives the caller's `$sp` and where its return address was saved, and
+//! the walk carries on from the caller. A function that hasn't saved `$ra`
+//! returns through the register, which only holds for the innermost frame.
+//! Without symbols there's no prologue to find, so the walk stops after
+//! following `$ra`.
+
+use std::fmt::Write;
+
+use lark_vm::cpu::{MemRw, Memory};
+
+use crate::isa;
+
+use super::{Debugger, MMIO_START};
+
+/// Stop walking after this many frames, in case of a loop.
+const MAX_FRAMES: usize = 64;
+
+/// How many words the Stack panel gets, starting a couple below `$sp`.
+const STACK_VIEW_WORDS: u16 = 32;
+const STACK_VIEW_BELOW: u16 = 2;
+
+/// A function that's been called and not yet returned.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: u16,
+    pub sp: u16,
+    /// Where the function saved `$ra` and the saved registers so far, by
+    /// register number.
+    pub saved: Vec<(u8, u16)>,
+}
+
+/// A word on the stack, for the Stack panel.
+#[derive(Debug, Clone)]
+pub struct StackWord {
+    pub addr: u16,
+    pub value: u16,
+    /// Offset from `$sp`.
+    pub offset: i16,
+    /// The register saved here and the frame that saved it, and where the
+    /// word points in ROM if it looks like a return address.
+    pub note: Option<String>,
+}
+
+/// What a function's prologue has done by some point.
+#[derive(Default)]
+struct Prologue {
+    /// How far `$sp` has moved since the function was entered.
+    sp_moved: i16,
+    /// Registers saved, by offset from `$sp` on entry.
+    saved: Vec<(u8, i16)>,
+}
+
+impl Debugger {
+    /// The frames from the innermost (at `pc`) outwards.
+    pub(super) fn backtrace(&mut self) -> Vec<Frame> {
+        self.handle_signals();
+        let mut frames = Vec::new();
+        let (mut pc, mut sp) = (self.cpu.pc, self.reg_value(isa::SP));
+        let mut ra = Some(self.reg_value(isa::RA));
+        while frames.len() < MAX_FRAMES {
+            let start = self.symbols.function_start(pc);
+            let prologue = match start {
+                Some(start) => self.prologue(start, pc),
+                None => Prologue::default(),
+            };
+            let entry_sp = sp.wrapping_add_signed(prologue.sp_moved.wrapping_neg());
+            let saved = prologue
+                .saved
+                .iter()
+                .map(|&(reg, offset)| (reg, entry_sp.wrapping_add_signed(offset)))
+                .collect::<Vec<_>>();
+            let ret = match saved.iter().find(|&&(reg, _)| reg == isa::RA) {
+                Some(&(_, slot)) if slot < MMIO_START => Some(self.cpu.mem.read_s16(slot).as_u16()),
+                Some(_) => None,
+                None => ra.take(),
+            };
+            frames.push(Frame { pc, sp, saved });
+
+            match ret {
+                Some(ret) if self.is_return_addr(ret) && entry_sp >= sp => {
+                    (pc, sp) = (ret, entry_sp);
+                }
+                _ => break,
+            }
+        }
+        self.discard_signals();
+        frames
+    }
+
+    /// Scans the prologue of the function at `start`, up to `until`.
+    fn prologue(&mut self, start: u16, until: u16) -> Prologue {
+        let mut prologue = Prologue::default();
+        let mut addr = start;
+        while addr < until {
+            let bytes = [0, 1, 2].map(|i| self.cpu.mem.read_u8(addr.wrapping_add(i)));
+            let opcode = isa::opcode(bytes[0]);
+            let Some((_, form)) = isa::lookup(opcode) else {
+                break;
+            };
+            if matches!(
+                opcode,
+                isa::J | isa::JR | isa::JAL | isa::JRAL | isa::BT | isa::BF | isa::KRET | isa::HALT
+            ) {
+                break;
+            }
+            if let Some(delta) = isa::sp_adjustment(bytes) {
+                prologue.sp_moved = prologue.sp_moved.wrapping_add(delta);
+            }
+            let stored = isa::stored_reg(bytes).zip(isa::mem_operand(bytes));
+            if let Some((reg, operand)) = stored.filter(|(_, op)| op.base == isa::SP) {
+                let is_saved = self.reg_by_index(reg).is_some_and(|r| r.is_saved());
+                if reg == isa::RA || is_saved {
+                    let offset = prologue.sp_moved.wrapping_add(operand.offset);
+                    prologue.saved.retain(|&(r, _)| r != reg);
+                    prologue.saved.push((reg, offset));
+                }
+            }
+            addr = addr.wrapping_add(form.size());
+        }
+        prologue
+    }
+
+    /// Whether `addr` is in ROM, right after a `jal` or `jral`.
+    fn is_return_addr(&mut self, addr: u16) -> bool {
+        let rom = self.cpu.mem.rom.as_ref().len() as u16;
+        let start = Memory::ROM_START;
+        if addr < start.wrapping_add(4) || addr - start > rom {
+            return false;
+        }
+        let jal = isa::opcode(self.cpu.mem.read_u8(addr - 4));
+        let jral = isa::opcode(self.cpu.mem.read_u8(addr - 2));
+        jal == isa::JAL || jral == isa::JRAL
+    }
+
+    /// The words around `$sp`, with what the backtrace knows about them.
+    pub(super) fn stack_words(&mut self) -> Vec<StackWord> {
+        let frames = self.backtrace();
+        let sp = self.reg_value(isa::SP);
+        let first = sp.saturating_sub(STACK_VIEW_BELOW * 2);
+        self.handle_signals();
+        let words = (0..STACK_VIEW_WORDS)
+            .filter_map(|i| first.checked_add(i * 2))
+            .take_while(|&addr| addr < MMIO_START - 1)
+            .map(|addr| {
+                let value = self.cpu.mem.read_s16(addr).as_u16();
+                let saved = frames.iter().enumerate().find_map(|(n, frame)| {
+                    let &(reg, _) = frame.saved.iter().find(|&&(_, slot)| slot == addr)?;
+                    Some(format!("#{n} {}", isa::REG_NAMES[reg as usize]))
+                });
+                let ret = self
+                    .is_return_addr(value)
+                    .then(|| format!("-> {}", self.symbols.describe(value)));
+                let note = match (saved, ret) {
+                    (Some(saved), Some(ret)) => Some(format!("{saved} {ret}")),
+                    (saved, ret) => saved.or(ret),
+                };
+                StackWord {
+                    addr,
+                    value,
+                    offset: addr.wrapping_sub(sp) as i16,
+                    note,
+                }
+            })
+            .collect();
+        self.discard_signals();
+        words
+    }
+
+    /// `backtrace`
+    pub(super) fn print_backtrace(&mut self) {
+        for (n, frame) in self.backtrace().into_iter().enumerate() {
+            let mut line = format!(
+                "#{n:<2} 0x{:04X} in {}  ($sp = 0x{:04X})",
+                frame.pc,
+                self.symbols.describe(frame.pc),
+                frame.sp
+            );
+            if let Some(loc) = self.lines.line_at(frame.pc) {
+                write!(line, " at {}", self.lines.describe(loc)).unwrap();
+            }
+            self.cmd_info(line);
+            if !frame.saved.is_empty() {
+                let saved = frame
+                    .saved
+                    .iter()
+                    .map(|&(reg, slot)| format!("{} at 0x{slot:04X}", isa::REG_NAMES[reg as usize]))
+                    .collect::<Vec<_>>();
+                self.cmd_info(format!("     saved {}", saved.join(", ")));
+            }
+        }
+        if self.symbols.is_empty() {
+            self.cmd_info("  - No symbol table is loaded, so only `$ra` was followed".to_string());
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! `next`, `finish` and `until`, which run the CPU until some point in the
+//! calling convention is reached instead of stopping after one instruction,
+//! and `step-line`/`next-line`, which run until a new source line starts.
+//!
+//! Calls are `jal`/`jral`, which leave the return address in their link
+//! register, and returns are `jr` through a register for which
+//! `Reg::is_ret_related` holds. Call depth alone is fooled by recursion, so
+//! every goal also checks that `$sp` is back at (or above) where it started.
+
+use lark_vm::cpu::MemRw;
+
+use crate::isa;
+
+use super::{lines::SourceLine, Debugger};
+
+/// Where a run started by `next`, `finish` or `until` should stop. Checked
+/// after every instruction.
+pub enum StepGoal {
+    /// Stop once the call being stepped over returns to `ret_addr`.
+    Return { ret_addr: u16, sp: u16 },
+    /// Stop once the current function returns. `depth` counts calls that
+    /// haven't returned yet.
+    Finish { depth: usize, sp: u16 },
+    /// Stop at `addr` in the current frame or an outer one, or once the
+    /// current function returns.
+    Until { addr: u16, depth: usize, sp: u16 },
+    /// Stop at the start of a source line other than `from`. With `over`,
+    /// lines in functions called along the way don't count. Once the
+    /// current function returns, any line will do.
+    Line {
+        from: Option<SourceLine>,
+        over: bool,
+        depth: usize,
+        sp: u16,
+    },
+}
+
+/// How an instruction moves control between functions.
+#[derive(Debug, Clone, Copy)]
+pub enum Flow {
+    Call { link: u8 },
+    Return,
+    Other,
+}
+
+impl StepGoal {
+    /// `line` is the source line starting at `pc`, if any.
+    fn reached(&mut self, flow: Flow, pc: u16, sp: u16, line: Option<SourceLine>) -> bool {
+        match self {
+            StepGoal::Return {
+                ret_addr,
+                sp: call_sp,
+            } => pc == *ret_addr && sp >= *call_sp,
+            StepGoal::Finish {
+                depth,
+                sp: start_sp,
+            } => left_frame(depth, flow) && sp >= *start_sp,
+            StepGoal::Until {
+                addr,
+                depth,
+                sp: start_sp,
+            } => (left_frame(depth, flow) || pc == *addr) && sp >= *start_sp,
+            StepGoal::Line {
+                from,
+                over,
+                depth,
+                sp: start_sp,
+            } => {
+                let returned = match over {
+                    true => left_frame(depth, flow),
+                    false => matches!(flow, Flow::Return),
+                };
+                if returned {
+                    *from = None;
+                }
+                let in_frame = !*over || (*depth == 0 && sp >= *start_sp);
+                in_frame && line.is_some_and(|line| Some(line) != *from)
+            }
+        }
+    }
+}
+
+/// Tracks call depth across an instruction, returning true if it returned
+/// from the function the goal started in.
+fn left_frame(depth: &mut usize, flow: Flow) -> bool {
+    match flow {
+        Flow::Call { .. } => {
+            *depth += 1;
+            false
+        }
+        Flow::Return if *depth == 0 => true,
+        Flow::Return => {
+            *depth -= 1;
+            false
+        }
+        Flow::Other => false,
+    }
+}
+
+impl Debugger {
+    /// Classifies the instruction at `pc` before it executes.
+    pub(super) fn pending_flow(&mut self) -> Flow {
+        let pc = self.cpu.pc;
+        let bytes = [0, 1].map(|i| self.cpu.mem.read_u8(pc.wrapping_add(i)));
+        let reg = isa::first_reg(bytes);
+        match isa::opcode(bytes[0]) {
+            isa::JAL | isa::JRAL => Flow::Call { link: reg },
+            isa::JR if self.reg_by_index(reg).is_some_and(|r| r.is_ret_related()) => Flow::Return,
+            _ => Flow::Other,
+        }
+    }
+
+    /// `next`: like `step`, but runs a call to completion.
+    pub(super) fn step_over(&mut self) {
+        let Flow::Call { link } = self.pending_flow() else {
+            self.cmd_log("Stepping...".to_string());
+            self.step_watched();
+            return;
+        };
+
+        let sp = self.reg_value(isa::SP);
+        self.step_watched();
+        let ret_addr = self.reg_value(link);
+        self.cmd_log(format!("Stepping over call to {}...", self.describe_pc()));
+
+        // No `resume_pc` here: a breakpoint at the callee's entry should stop us.
+        self.step_goal = Some(StepGoal::Return { ret_addr, sp });
+        self.cpu_run_till_breakpoint = true;
+    }
+
+    /// `finish`: runs until the current function returns.
+    pub(super) fn finish(&mut self) {
+        self.cmd_log("Running until the current function returns...".to_string());
+        let sp = self.reg_value(isa::SP);
+        self.start_goal(StepGoal::Finish { depth: 0, sp });
+    }
+
+    /// `until`: runs until `addr` is reached in this frame, or the current
+    /// function returns.
+    pub(super) fn run_until(&mut self, addr: u16) {
+        self.cmd_log(format!(
+            "Running until pc={}...",
+            self.symbols.describe(addr)
+        ));
+        let sp = self.reg_value(isa::SP);
+        self.start_goal(StepGoal::Until { addr, depth: 0, sp });
+    }
+
+    /// `step-line` and `next-line`: runs until the next source line starts,
+    /// stepping over calls if `over` is set.
+    pub(super) fn step_line(&mut self, over: bool) {
+        if self.lines.is_empty() {
+            self.cmd_err("No line table is loaded for this ROM.".to_string());
+            return;
+        }
+        self.cmd_log("Running to the next source line...".to_string());
+        let goal = StepGoal::Line {
+            from: self.lines.line_at(self.cpu.pc),
+            over,
+            depth: 0,
+            sp: self.reg_value(isa::SP),
+        };
+        self.start_goal(goal);
+    }
+
+    fn start_goal(&mut self, goal: StepGoal) {
+        self.step_goal = Some(goal);
+        self.cpu_run_till_breakpoint = true;
+        self.resume_pc = Some(self.cpu.pc);
+    }
+
+    /// Called by the run loop after executing an instruction whose flow was
+    /// `flow`. Stops the run if the current step goal has been reached.
+    pub(super) fn check_step_goal(&mut self, flow: Flow) {
+        let (pc, sp) = (self.cpu.pc, self.reg_value(isa::SP));
+        let line = self.lines.line_starting_at(pc);
+        let Some(goal) = self.step_goal.as_mut() else {
+            return;
+        };
+
+        if !goal.reached(flow, pc, sp, line) {
+            return;
+        }
+
+        match goal {
+            StepGoal::Finish { .. } => {
+                let rv = self.reg_value(isa::RV);
+                self.cmd_log(format!(
+                    "Returned to pc={}, $rv = 0x{rv:04X} ({rv})",
+                    self.symbols.describe(pc)
+                ));
+            }
+            StepGoal::Line { .. } => {
+                let line = self.lines.describe(line.unwrap());
+                self.cmd_log(format!(
+                    "Stopped at {line} (pc={})",
+                    self.symbols.describe(pc)
+                ));
+            }
+            StepGoal::Return { .. } | StepGoal::Until { .. } => {
+                self.cmd_log(format!("Stopped at pc={}", self.symbols.describe(pc)));
+            }
+        }
+
+        self.stop_run();
+    }
+}
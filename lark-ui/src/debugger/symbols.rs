@@ -0,0 +1,224 @@
+//! Labels and their addresses, so the console and disassembly can say
+//! `main+0x6` instead of `0x0806`.
+//!
+//! A symbol file has one `NAME = VALUE` per line, the way customasm's
+//! `symbols` output format writes them; local labels are `global.local`.
+//! When a ROM is loaded, `prog.sym` next to `prog.rom` is read if there is
+//! one, and assembling `prog.lark` writes it.
+
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use lark_vm::cpu::{self, Memory};
+
+use super::commands::parse_number;
+
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    by_name: BTreeMap<String, u16>,
+    /// One name per address, preferring global labels over local ones.
+    by_addr: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub const EXTENSION: &'static str = "sym";
+
+    pub fn from_labels(labels: impl IntoIterator<Item = (String, u16)>) -> Self {
+        let mut syms = Self::default();
+        for (name, addr) in labels {
+            syms.insert(name, addr);
+        }
+        syms
+    }
+
+    fn insert(&mut self, name: String, addr: u16) {
+        let replace = self
+            .by_addr
+            .get(&addr)
+            .is_none_or(|old| old.contains('.') && !name.contains('.'));
+        if replace {
+            self.by_addr.insert(addr, name.clone());
+        }
+        self.by_name.insert(name, addr);
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut labels = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line
+                .split_once('=')
+                .and_then(|(name, value)| Some((name.trim(), parse_number(value.trim())?)));
+            match parsed {
+                Some((name, addr)) if !name.is_empty() => labels.push((name.to_owned(), addr)),
+                _ => return Err(format!("line {}: Expected `<NAME> = <ADDR>`", i + 1)),
+            }
+        }
+        Ok(Self::from_labels(labels))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading `{}`: {e}", path.display()))?;
+        Self::parse(&src).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = String::new();
+        for (addr, name) in self.iter() {
+            writeln!(out, "{name} = 0x{addr:04x}").unwrap();
+        }
+        std::fs::write(path, out).map_err(|e| format!("Error writing `{}`: {e}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /// The label at exactly `addr`.
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    /// Every symbol, ordered by address.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        let mut all = self
+            .by_name
+            .iter()
+            .map(|(name, &addr)| (addr, name.as_str()))
+            .collect::<Vec<_>>();
+        all.sort();
+        all.into_iter()
+    }
+
+    /// The label `addr` is at or in, and how far into it. Only code
+    /// addresses get an offset from the nearest label before them; other
+    /// addresses need a label right on them.
+    pub fn locate(&self, addr: u16) -> Option<(&str, u16)> {
+        if let Some(name) = self.label_at(addr) {
+            return Some((name, 0));
+        }
+        let rom = Memory::ROM_START as usize..Memory::ROM_START as usize + cpu::ROM_SIZE;
+        let (&base, name) = self.by_addr.range(..addr).next_back()?;
+        match rom.contains(&(addr as usize)) && rom.contains(&(base as usize)) {
+            true => Some((name, addr - base)),
+            false => None,
+        }
+    }
+
+    /// The nearest global label at or before `addr`, taken to be the start
+    /// of the function `addr` is in.
+    pub fn function_start(&self, addr: u16) -> Option<u16> {
+        self.by_name
+            .iter()
+            .filter(|(name, &a)| !name.contains('.') && a <= addr)
+            .map(|(_, &a)| a)
+            .max()
+    }
+
+    /// `addr` as `label` or `label+0xN`, or in hex if it has no label.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.locate(addr) {
+            Some((name, 0)) => name.to_owned(),
+            Some((name, offset)) => format!("{name}+0x{offset:x}"),
+            None => format!("0x{addr:04x}"),
+        }
+    }
+
+    /// `addr` in hex, followed by `<label+0xN>` if there's a label for it.
+    pub fn annotate(&self, addr: u16) -> String {
+        match self.locate(addr) {
+            Some(_) => format!("0x{addr:04X} <{}>", self.describe(addr)),
+            None => format!("0x{addr:04X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: u16 = Memory::ROM_START;
+
+    fn sample() -> Symbols {
+        Symbols::from_labels([
+            ("main".to_owned(), ROM),
+            ("main.loop".to_owned(), ROM + 4),
+            ("helper.start".to_owned(), ROM + 0x10),
+            ("helper".to_owned(), ROM + 0x10),
+            ("buffer".to_owned(), 0x0100),
+        ])
+    }
+
+    #[test]
+    fn parse() {
+        let src = "; made by hand\nmain = 0x0800\n\nmain.loop = 2052 ; a comment\nx=0b11\n";
+        let syms = Symbols::parse(src).unwrap();
+        assert_eq!(syms.len(), 3);
+        assert_eq!(syms.lookup("main"), Some(0x0800));
+        assert_eq!(syms.lookup("main.loop"), Some(2052));
+        assert_eq!(syms.lookup("x"), Some(3));
+        assert_eq!(syms.lookup("y"), None);
+
+        for bad in ["main", "main = ", "= 0x10", "main = lots"] {
+            assert_eq!(
+                Symbols::parse(bad).err().as_deref(),
+                Some("line 1: Expected `<NAME> = <ADDR>`"),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn prefers_global_labels() {
+        let syms = sample();
+        assert_eq!(syms.label_at(ROM + 0x10), Some("helper"));
+        assert_eq!(syms.label_at(ROM + 4), Some("main.loop"));
+        assert_eq!(syms.label_at(ROM + 5), None);
+    }
+
+    #[test]
+    fn describe() {
+        let syms = sample();
+        assert_eq!(syms.describe(ROM), "main");
+        assert_eq!(syms.describe(ROM + 6), "main.loop+0x2");
+        assert_eq!(syms.describe(ROM + 0x13), "helper+0x3");
+        // Data addresses only get a name if one is right on them.
+        assert_eq!(syms.describe(0x0100), "buffer");
+        assert_eq!(syms.describe(0x0102), "0x0102");
+        assert_eq!(syms.annotate(ROM + 6), "0x0806 <main.loop+0x2>");
+        assert_eq!(syms.annotate(0x0102), "0x0102");
+    }
+
+    #[test]
+    fn function_start() {
+        let syms = sample();
+        assert_eq!(syms.function_start(ROM + 6), Some(ROM));
+        assert_eq!(syms.function_start(ROM + 0x10), Some(ROM + 0x10));
+        assert_eq!(syms.function_start(0x00FF), None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("lark-symbols-{}.sym", std::process::id()));
+        let syms = sample();
+        syms.save(&path).unwrap();
+        let loaded = Symbols::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            syms.iter().collect::<Vec<_>>()
+        );
+    }
+}
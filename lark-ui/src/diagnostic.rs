@@ -0,0 +1,265 @@
+//! Errors in a source file, from the assembler or the Meadowlark compiler,
+//! with enough position information to show the offending code:
+//!
+//! ```text
+//! error: Wrong operands for `li`
+//!   --> prog.lark:12:5
+//!    |
+//! 12 |     li $t0
+//!    |     ^^^^^^
+//!    = note: usage: li <REG>, <IMM16>
+//! ```
+
+use std::{fmt, ops::Range, path::PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    /// 1-based, or 0 if the error isn't about any one line.
+    pub line: usize,
+    /// 1-based column (in chars) where the span starts, or 0 if unknown.
+    pub col: usize,
+    /// Length of the span in chars.
+    pub len: usize,
+    pub msg: String,
+    pub notes: Vec<String>,
+    /// The text of `line`, for the excerpt.
+    pub source: Option<String>,
+}
+
+impl Diagnostic {
+    /// A diagnostic about a whole line, or the whole file if `line` is 0.
+    pub fn new(path: impl Into<PathBuf>, line: usize, msg: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            line,
+            col: 0,
+            len: 0,
+            msg: msg.into(),
+            notes: Vec::new(),
+            source: None,
+        }
+    }
+
+    /// Attaches the text of the line. Without a span, the caret underlines
+    /// everything but the indentation.
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        let source = source.into();
+        if self.col == 0 {
+            let indent = source.chars().take_while(|c| c.is_whitespace()).count();
+            self.col = indent + 1;
+            self.len = source.trim().chars().count();
+        }
+        self.source = Some(source);
+        self
+    }
+
+    /// Narrows the span to the first occurrence of `needle` on the line.
+    pub fn with_span_of(mut self, needle: &str) -> Self {
+        let found = self.source.as_deref().and_then(|src| src.find(needle));
+        if let (Some(src), Some(start)) = (&self.source, found) {
+            if !needle.is_empty() {
+                self.col = src[..start].chars().count() + 1;
+                self.len = needle.chars().count();
+            }
+        }
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// A diagnostic about `len` chars from `line:col`, showing the line as
+    /// read from `path`.
+    pub fn at(
+        path: impl Into<PathBuf>,
+        line: usize,
+        col: usize,
+        len: usize,
+        msg: impl Into<String>,
+    ) -> Self {
+        let path = path.into();
+        let source = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|src| src.lines().nth(line.wrapping_sub(1)).map(str::to_owned));
+        let mut diag = Self::new(path, line, msg);
+        diag.col = col;
+        diag.len = len;
+        if let Some(source) = source {
+            diag = diag.with_source(source);
+        }
+        diag
+    }
+
+    /// Makes a diagnostic out of a Meadowlark compiler error, using the byte
+    /// range it points at in `path` if it has one, and otherwise any position
+    /// at the start of its text.
+    pub fn from_compile_error(path: impl Into<PathBuf>, e: &meadowlark::CompileError) -> Self {
+        let path = path.into();
+        let text = e.to_string();
+        let msg = parse_location(&text).map_or_else(|| text.trim().to_owned(), |(.., msg)| msg);
+        let src = std::fs::read_to_string(&path).unwrap_or_default();
+        match e.span().and_then(|span| span_position(&src, span)) {
+            Some((line, col, len)) => Self::at(path, line, col, len, msg),
+            None => Self::from_message(path, &text),
+        }
+    }
+
+    /// Makes a diagnostic out of an error that's only available as text. A
+    /// leading `line:col:` or `file:line:col:` is taken as the position, and
+    /// the line is read from `path` to show it.
+    pub fn from_message(path: impl Into<PathBuf>, text: &str) -> Self {
+        match parse_location(text) {
+            Some((line, col, msg)) => Self::at(path, line, col, usize::from(col > 0), msg),
+            None => Self::new(path, 0, text.trim()),
+        }
+    }
+
+    /// The message, an excerpt of the line with the span underlined, and the
+    /// notes, one entry per line of output.
+    pub fn render(&self) -> Vec<String> {
+        let mut out = vec![format!("error: {}", self.msg)];
+        let loc = self.location();
+        let gutter = self.line.to_string().len();
+        let pad = " ".repeat(gutter);
+        out.push(format!("{pad}--> {loc}"));
+        if let Some(src) = &self.source {
+            let src = src.replace('\t', " ");
+            out.push(format!("{pad} |"));
+            out.push(format!("{} | {src}", self.line));
+            if self.col > 0 {
+                let under = " ".repeat(self.col - 1) + &"^".repeat(self.len.max(1));
+                out.push(format!("{pad} | {under}"));
+            }
+        }
+        for note in &self.notes {
+            out.push(format!("{pad} = note: {note}"));
+        }
+        out
+    }
+
+    /// `path:line:col`, leaving off what isn't known.
+    pub fn location(&self) -> String {
+        let mut loc = self.path.display().to_string();
+        if self.line > 0 {
+            loc += &format!(":{}", self.line);
+            if self.col > 0 {
+                loc += &format!(":{}", self.col);
+            }
+        }
+        loc
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location(), self.msg)
+    }
+}
+
+/// The 1-based line and column of the byte range `span` in `src`, and its
+/// length in chars, cut short at the end of the line.
+fn span_position(src: &str, span: Range<usize>) -> Option<(usize, usize, usize)> {
+    let before = src.get(..span.start)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let col = before[line_start..].chars().count() + 1;
+    let rest = src.get(span.start..span.end.max(span.start))?;
+    let len = rest.split('\n').next().unwrap_or("").chars().count();
+    Some((line, col, len))
+}
+
+/// Finds `line:col: msg` (possibly after a file name and `:`) in `text`.
+fn parse_location(text: &str) -> Option<(usize, usize, String)> {
+    let parts = text.splitn(4, ':').map(str::trim).collect::<Vec<_>>();
+    let num = |s: &str| s.parse::<usize>().ok().filter(|&n| n > 0);
+    match parts[..] {
+        [_, line, col, msg] if num(line).is_some() && num(col).is_some() => {
+            Some((num(line)?, num(col)?, msg.to_owned()))
+        }
+        [line, col, ..] if num(line).is_some() && num(col).is_some() => {
+            let msg = text.splitn(3, ':').nth(2)?.trim();
+            Some((num(line)?, num(col)?, msg.to_owned()))
+        }
+        [_, line, ..] if num(line).is_some() => {
+            let msg = text.splitn(3, ':').nth(2)?.trim();
+            Some((num(line)?, 0, msg.to_owned()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() {
+        assert_eq!(
+            parse_location("prog.meadow:3:7: expected `;`"),
+            Some((3, 7, "expected `;`".to_owned()))
+        );
+        assert_eq!(
+            parse_location("3:7: expected `;`"),
+            Some((3, 7, "expected `;`".to_owned()))
+        );
+        assert_eq!(
+            parse_location("prog.meadow:3: unknown type"),
+            Some((3, 0, "unknown type".to_owned()))
+        );
+        assert_eq!(parse_location("error: no main function"), None);
+        assert_eq!(parse_location("prog.meadow:0:1: bad"), None);
+    }
+
+    #[test]
+    fn spans() {
+        let src = "let x = 1;\nlet y = é + z;\n";
+        assert_eq!(span_position(src, 0..3), Some((1, 1, 3)));
+        let z = src.find('z').unwrap();
+        assert_eq!(span_position(src, z..z + 1), Some((2, 13, 1)));
+        // Spans running onto the next line are cut at the end of the first.
+        assert_eq!(span_position(src, 4..14), Some((1, 5, 6)));
+        assert_eq!(span_position(src, 100..101), None);
+    }
+
+    #[test]
+    fn render() {
+        let diag = Diagnostic::new("prog.lark", 12, "Wrong operands for `li`")
+            .with_source("    li $t0")
+            .with_note("usage: li <REG>, <IMM16>");
+        assert_eq!(
+            diag.render(),
+            [
+                "error: Wrong operands for `li`",
+                "  --> prog.lark:12:5",
+                "   |",
+                "12 |     li $t0",
+                "   |     ^^^^^^",
+                "   = note: usage: li <REG>, <IMM16>",
+            ]
+        );
+
+        let diag = Diagnostic::new("prog.lark", 3, "Unknown label `lop`")
+            .with_source("\tj lop")
+            .with_span_of("lop");
+        assert_eq!(
+            diag.render(),
+            [
+                "error: Unknown label `lop`",
+                " --> prog.lark:3:4",
+                "  |",
+                "3 |  j lop",
+                "  |    ^^^",
+            ]
+        );
+
+        let diag = Diagnostic::new("prog.lark", 0, "Error reading file");
+        assert_eq!(
+            diag.render(),
+            ["error: Error reading file", " --> prog.lark"]
+        );
+        assert_eq!(diag.to_string(), "prog.lark: Error reading file");
+    }
+}
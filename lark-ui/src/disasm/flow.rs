@@ -0,0 +1,328 @@
+//! Telling code from data by following control flow, rather than decoding a
+//! ROM front to back.
+//!
+//! Decoding starts at the entry points and runs on to the next instruction
+//! until a `j`, `jr`, `halt` or `kret`, picking up `j`/`jal`/`bt`/`bf`
+//! targets as it goes. Everything it doesn't reach is data, so strings and
+//! tables don't turn into garbage instructions, and something undecodable
+//! only ends the path that ran into it. Code only reached through a register
+//! (a `jr` into a jump table, say) shows up as data unless it's an entry
+//! point.
+
+use std::collections::BTreeMap;
+
+use lark_vm::cpu::{instr::Instr, Memory};
+
+use crate::{debugger::Symbols, isa};
+
+/// A run of zeros at least this long is shown as `.zero` rather than bytes.
+const MIN_ZEROS: usize = 16;
+
+/// A run of printable characters at least this long is shown as `.ascii`.
+const MIN_ASCII: usize = 4;
+
+/// How many bytes a `.byte` item holds.
+const BYTES_PER_ITEM: usize = 4;
+
+#[derive(Debug)]
+pub enum ItemKind {
+    Instr(Instr),
+    Bytes,
+    Ascii,
+    Zeros,
+}
+
+/// An instruction or a run of data at `addr`.
+#[derive(Debug)]
+pub struct Item {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: ItemKind,
+}
+
+impl Item {
+    pub fn is_code(&self) -> bool {
+        matches!(self.kind, ItemKind::Instr(_))
+    }
+
+    /// Where the item goes if it's a jump or branch.
+    pub fn target(&self) -> Option<u16> {
+        self.is_code().then_some(())?;
+        let mut word = [0; 4];
+        let len = self.bytes.len().min(4);
+        word[..len].copy_from_slice(&self.bytes[..len]);
+        isa::jump_target(word, self.addr)
+    }
+
+    /// Mnemonic and operands: the instruction's own, or a `.byte`, `.ascii`
+    /// or `.zero` directive for data.
+    pub fn text(&self) -> (String, String) {
+        match &self.kind {
+            ItemKind::Instr(instr) => {
+                let text = instr.to_string();
+                match text.split_once('\t') {
+                    Some((op, args)) => (op.to_owned(), args.trim().to_owned()),
+                    None => (text.trim().to_owned(), String::new()),
+                }
+            }
+            ItemKind::Bytes => {
+                let bytes = self
+                    .bytes
+                    .iter()
+                    .map(|b| format!("0x{b:02x}"))
+                    .collect::<Vec<_>>();
+                (".byte".to_owned(), bytes.join(", "))
+            }
+            ItemKind::Ascii => (".ascii".to_owned(), quote(&self.bytes)),
+            ItemKind::Zeros => (".zero".to_owned(), self.bytes.len().to_string()),
+        }
+    }
+}
+
+/// `bytes` as a double-quoted string, which only needs quotes and
+/// backslashes escaped since they're all printable.
+pub fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from('"');
+    for &b in bytes {
+        if b == b'"' || b == b'\\' {
+            out.push('\\');
+        }
+        out.push(b as char);
+    }
+    out.push('"');
+    out
+}
+
+/// Decodes the instruction at the start of `bytes`. All-zero bytes are
+/// padding rather than `exn 0`.
+fn decode(bytes: &[u8]) -> Option<Instr> {
+    let (_, form) = isa::lookup(isa::opcode(*bytes.first()?))?;
+    let bytes = bytes.get(..form.size() as usize)?;
+    if bytes.iter().all(|&b| b == 0) {
+        return None;
+    }
+    let mut instrs = Vec::new();
+    Instr::disassemble(&mut instrs, bytes).ok()?;
+    match instrs.len() {
+        1 => instrs.pop(),
+        _ => None,
+    }
+}
+
+/// Whether execution never goes on to the instruction after one with
+/// `opcode`.
+fn ends_flow(opcode: u8) -> bool {
+    matches!(opcode, isa::J | isa::JR | isa::HALT | isa::KRET)
+}
+
+/// Splits `rom` (loaded at `Memory::ROM_START`) into code reachable from
+/// `entries` and data. Data is also split wherever `symbols` has a label,
+/// so every label lands at the start of an item.
+pub fn analyze(rom: &[u8], entries: impl IntoIterator<Item = u16>, symbols: &Symbols) -> Vec<Item> {
+    let offset_of = |addr: u16| {
+        (addr as usize)
+            .checked_sub(Memory::ROM_START as usize)
+            .filter(|&offset| offset < rom.len())
+    };
+
+    // Instructions by offset, and which bytes they cover.
+    let mut code = BTreeMap::new();
+    let mut covered = vec![false; rom.len()];
+    let mut work = entries
+        .into_iter()
+        .filter_map(offset_of)
+        .collect::<Vec<_>>();
+    while let Some(mut offset) = work.pop() {
+        while offset < rom.len() && !covered[offset] {
+            let Some(instr) = decode(&rom[offset..]) else {
+                break;
+            };
+            let size = instr.instr_size() as usize;
+            let span = offset..offset + size;
+            if covered[span.clone()].iter().any(|&c| c) {
+                break;
+            }
+            covered[span].fill(true);
+
+            let opcode = isa::opcode(rom[offset]);
+            let item = Item {
+                addr: Memory::ROM_START + offset as u16,
+                bytes: rom[offset..offset + size].to_vec(),
+                kind: ItemKind::Instr(instr),
+            };
+            if let Some(target) = item.target().and_then(offset_of) {
+                work.push(target);
+            }
+            code.insert(offset, item);
+            if ends_flow(opcode) {
+                break;
+            }
+            offset += size;
+        }
+    }
+
+    let mut items = Vec::with_capacity(code.len());
+    let mut offset = 0;
+    while offset < rom.len() {
+        if let Some(item) = code.remove(&offset) {
+            offset += item.bytes.len();
+            items.push(item);
+            continue;
+        }
+        let gap_end = (offset..rom.len())
+            .find(|&o| covered[o])
+            .unwrap_or(rom.len());
+        data_items(rom, offset..gap_end, symbols, &mut items);
+        offset = gap_end;
+    }
+    items
+}
+
+/// Breaks `rom[range]` into data items, first at labels and then into runs
+/// of zeros, text and other bytes.
+fn data_items(rom: &[u8], range: std::ops::Range<usize>, symbols: &Symbols, items: &mut Vec<Item>) {
+    let addr_of = |offset: usize| Memory::ROM_START + offset as u16;
+    let run = |from: usize, end: usize, pred: fn(u8) -> bool| {
+        rom[from..end].iter().take_while(|&&b| pred(b)).count()
+    };
+    let zeros = |from, end| Some(run(from, end, |b| b == 0)).filter(|&n| n >= MIN_ZEROS);
+    let text = |from, end| {
+        Some(run(from, end, |b| b.is_ascii_graphic() || b == b' ')).filter(|&n| n >= MIN_ASCII)
+    };
+
+    let mut offset = range.start;
+    while offset < range.end {
+        let end = (offset + 1..range.end)
+            .find(|&o| symbols.label_at(addr_of(o)).is_some())
+            .unwrap_or(range.end);
+        while offset < end {
+            let (kind, len) = if let Some(len) = zeros(offset, end) {
+                (ItemKind::Zeros, len)
+            } else if let Some(len) = text(offset, end) {
+                (ItemKind::Ascii, len)
+            } else {
+                let mut len = 1;
+                while len < BYTES_PER_ITEM
+                    && offset + len < end
+                    && zeros(offset + len, end).is_none()
+                    && text(offset + len, end).is_none()
+                {
+                    len += 1;
+                }
+                (ItemKind::Bytes, len)
+            };
+            items.push(Item {
+                addr: addr_of(offset),
+                bytes: rom[offset..offset + len].to_vec(),
+                kind,
+            });
+            offset += len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: u16 = Memory::ROM_START;
+
+    /// Each item's address and size, and what it is: `code` or the data
+    /// directive it'd be printed as.
+    fn summary(items: &[Item]) -> Vec<(u16, usize, String)> {
+        items
+            .iter()
+            .map(|item| {
+                let what = match item.is_code() {
+                    true => "code".to_owned(),
+                    false => {
+                        let (op, args) = item.text();
+                        format!("{op} {args}")
+                    }
+                };
+                (item.addr, item.bytes.len(), what)
+            })
+            .collect()
+    }
+
+    fn row(addr: u16, len: usize, what: &str) -> (u16, usize, String) {
+        (addr, len, what.to_owned())
+    }
+
+    #[test]
+    fn follows_jumps_and_calls() {
+        #[rustfmt::skip]
+        let rom = [
+            0x42, 0x40, 0x00, 0xC0, // main:  li    $t0, 3
+            0xA6, 0x64, 0x01,       // .loop: subi  $t0, $t0, 1
+            0x32, 0x7F, 0xFF, 0x40, //        bt    $t0, .loop
+            0x28, 0x80, 0x02, 0x80, //        jal   $ra, func
+            0x04,                   //        halt
+            b'H', b'e', b'l', b'l', b'o', // msg
+            0x24, 0x80,             // func:  jr    $ra
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x01, 0x02,
+        ];
+        let symbols = Symbols::from_labels([
+            ("main".to_owned(), ROM),
+            ("main.loop".to_owned(), ROM + 4),
+            ("msg".to_owned(), ROM + 0x10),
+            ("func".to_owned(), ROM + 0x15),
+        ]);
+        let items = analyze(&rom, [ROM], &symbols);
+        assert_eq!(
+            summary(&items),
+            [
+                row(ROM, 4, "code"),
+                row(ROM + 4, 3, "code"),
+                row(ROM + 7, 4, "code"),
+                row(ROM + 0xB, 4, "code"),
+                row(ROM + 0xF, 1, "code"),
+                row(ROM + 0x10, 5, r#".ascii "Hello""#),
+                row(ROM + 0x15, 2, "code"),
+                row(ROM + 0x17, 16, ".zero 16"),
+                row(ROM + 0x27, 2, ".byte 0x01, 0x02"),
+            ]
+        );
+        assert_eq!(items[2].target(), Some(ROM + 4));
+        assert_eq!(items[3].target(), Some(ROM + 0x15));
+        assert_eq!(items[0].target(), None);
+    }
+
+    #[test]
+    fn unreached_code_is_data() {
+        #[rustfmt::skip]
+        let rom = [
+            0x20, 0x00, 0x1C,       //       j     end
+            0x42, 0x40, 0x00, 0xC0, //       li    $t0, 3
+            0x04,                   // end:  halt
+        ];
+        let items = analyze(&rom, [ROM], &Symbols::default());
+        assert_eq!(
+            summary(&items),
+            [
+                row(ROM, 3, "code"),
+                row(ROM + 3, 4, ".byte 0x42, 0x40, 0x00, 0xc0"),
+                row(ROM + 7, 1, "code"),
+            ]
+        );
+
+        // Another entry point, like a line in the line table, makes it code.
+        let items = analyze(&rom, [ROM, ROM + 3], &Symbols::default());
+        assert!(items.iter().all(Item::is_code));
+    }
+
+    #[test]
+    fn labels_split_data() {
+        let symbols = Symbols::from_labels([("second".to_owned(), ROM + 4)]);
+        let items = analyze(b"abcdefgh\"\\\x01", [], &symbols);
+        assert_eq!(
+            summary(&items),
+            [
+                row(ROM, 4, r#".ascii "abcd""#),
+                row(ROM + 4, 6, r#".ascii "efgh\"\\""#),
+                row(ROM + 10, 1, ".byte 0x01"),
+            ]
+        );
+    }
+}
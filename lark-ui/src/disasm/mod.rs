@@ -0,0 +1,271 @@
+//! Disassembly, for the debugger's Disassembly tab and for `lark disasm`,
+//! which prints an objdump-style listing of a ROM, or with `--lark`, assembly
+//! source that assembles back into the same ROM.
+//!
+//! `flow` works out which parts of the ROM are code. Labels come from the
+//! ROM's symbol file if it has one. Jump and branch targets are worked out
+//! from the encoded offsets, so they're resolved even without symbols; in
+//! `--lark` output, targets with no label get a generated `L_XXXX` one (local
+//! to the label before it, if any), and data becomes `#d8` and `#d`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use lark_vm::cpu::Memory;
+
+use crate::{
+    build::{self, EXIT_ERROR, EXIT_OK},
+    cli::DisasmOpts,
+    debugger::{LineTable, Symbols},
+};
+
+pub use self::flow::{analyze, quote, Item, ItemKind};
+
+mod flow;
+
+/// The longest instruction, in bytes, and so how many bytes of data a
+/// listing line shows.
+const MAX_INSTR_SIZE: usize = 4;
+
+/// Width of the raw bytes column.
+const BYTES_WIDTH: usize = MAX_INSTR_SIZE * 3 - 1;
+
+/// How many bytes a `#d8` line in `--lark` output holds.
+const DATA_PER_LINE: usize = 8;
+
+pub fn run(opts: &DisasmOpts) -> ExitCode {
+    let built = match build::build(&opts.build.program, opts.build.out_dir.as_deref()) {
+        Ok(built) => built,
+        Err(diags) => {
+            build::print_diagnostics(&diags);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    let output = match opts.lark {
+        true => disassemble_lark(&built.rom),
+        false => disassemble(&built.rom),
+    };
+    match output {
+        Ok(text) => {
+            print!("{text}");
+            ExitCode::from(EXIT_OK)
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+/// A ROM image split into code and data.
+struct Rom {
+    path: PathBuf,
+    symbols: Symbols,
+    items: Vec<Item>,
+}
+
+impl Rom {
+    /// Reads the ROM at `path` and the symbols and line table beside it, if
+    /// any. Code is found from the start of the ROM and from every line in
+    /// the line table.
+    fn load(path: &Path) -> Result<Self, String> {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("Error reading `{}`: {e}", path.display()))?;
+        let sym_path = path.with_extension(Symbols::EXTENSION);
+        let symbols = match sym_path.exists() {
+            true => Symbols::load(&sym_path)?,
+            false => Symbols::default(),
+        };
+        let lines_path = path.with_extension(LineTable::EXTENSION);
+        let lines = match lines_path.exists() {
+            true => LineTable::load(&lines_path)?,
+            false => LineTable::default(),
+        };
+
+        let entries = std::iter::once(Memory::ROM_START).chain(lines.addrs());
+        let items = analyze(&bytes, entries, &symbols);
+        Ok(Self {
+            path: path.to_path_buf(),
+            symbols,
+            items,
+        })
+    }
+
+    /// Whether an item starts at `addr`, meaning a label can go there.
+    fn is_boundary(&self, addr: u16) -> bool {
+        self.items
+            .binary_search_by_key(&addr, |item| item.addr)
+            .is_ok()
+    }
+}
+
+/// `args` with its last operand (a jump's target) replaced by `target`.
+fn with_target(args: &str, target: &str) -> String {
+    match args.rsplit_once(", ") {
+        Some((init, _)) => format!("{init}, {target}"),
+        None => target.to_owned(),
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `#d8` lines for `bytes`.
+fn data_directives(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(DATA_PER_LINE)
+        .map(|chunk| {
+            let bytes = chunk
+                .iter()
+                .map(|b| format!("0x{b:02x}"))
+                .collect::<Vec<_>>();
+            format!("#d8 {}", bytes.join(", "))
+        })
+        .collect()
+}
+
+/// The listing for the ROM at `path`: address, raw bytes and instruction on
+/// each line, with each label on a line of its own before what it marks.
+/// Data only shows its first few bytes in the bytes column.
+pub fn disassemble(path: &Path) -> Result<String, String> {
+    let rom = Rom::load(path)?;
+
+    let mut out = String::new();
+    for item in &rom.items {
+        if let Some(label) = rom.symbols.label_at(item.addr) {
+            writeln!(out, "\n{label}:").unwrap();
+        }
+        let (op, mut args) = item.text();
+        if let Some(target) = item.target() {
+            let mut text = format!("0x{target:04X}");
+            if rom.symbols.locate(target).is_some() {
+                write!(text, " <{}>", rom.symbols.describe(target)).unwrap();
+            }
+            args = with_target(&args, &text);
+        }
+        let addr = item.addr;
+        let shown = &item.bytes[..item.bytes.len().min(MAX_INSTR_SIZE)];
+        let bytes = hex_bytes(shown);
+        writeln!(out, "0x{addr:04X}:  {bytes:<BYTES_WIDTH$}  {op:<8}{args}").unwrap();
+    }
+    Ok(out)
+}
+
+/// Every label to put in `--lark` output, by address: the ones from the
+/// symbol file plus generated ones for jump targets without one.
+fn lark_labels(rom: &Rom) -> BTreeMap<u16, Vec<String>> {
+    let mut labels = BTreeMap::<u16, Vec<String>>::new();
+    for (addr, name) in rom.symbols.iter() {
+        if rom.is_boundary(addr) {
+            labels.entry(addr).or_default().push(name.to_owned());
+        }
+    }
+
+    // In address order, so a generated global label is in place before the
+    // scope of anything after it is worked out.
+    let mut targets = rom
+        .items
+        .iter()
+        .filter_map(Item::target)
+        .filter(|&target| rom.is_boundary(target))
+        .collect::<Vec<_>>();
+    targets.sort();
+    targets.dedup();
+    for target in targets {
+        if labels.contains_key(&target) {
+            continue;
+        }
+        let name = match scope_at(&labels, target) {
+            Some(scope) => format!("{scope}.L_{target:04X}"),
+            None => format!("L_{target:04X}"),
+        };
+        labels.insert(target, vec![name]);
+    }
+    labels
+}
+
+/// The global label in effect at `addr`: the last one at or before it.
+fn scope_at(labels: &BTreeMap<u16, Vec<String>>, addr: u16) -> Option<String> {
+    labels
+        .range(..=addr)
+        .rev()
+        .find_map(|(_, names)| names.iter().rev().find(|name| !name.contains('.')))
+        .cloned()
+}
+
+/// How to write `name` where `scope` is the current global label.
+fn label_ref(name: &str, scope: &str) -> String {
+    match name
+        .strip_prefix(scope)
+        .and_then(|rest| rest.strip_prefix('.'))
+    {
+        Some(local) if !scope.is_empty() => format!(".{local}"),
+        _ => name.to_owned(),
+    }
+}
+
+/// Assembly source for the ROM at `path`, which `lark build` or customasm
+/// assembles back into the same bytes.
+pub fn disassemble_lark(path: &Path) -> Result<String, String> {
+    let rom = Rom::load(path)?;
+    let labels = lark_labels(&rom);
+    // The label a jump to an address should use, preferring the symbol
+    // file's choice where there's more than one.
+    let target_label = |addr| {
+        let names = labels.get(&addr)?;
+        rom.symbols
+            .label_at(addr)
+            .filter(|name| names.iter().any(|n| n == name))
+            .or(names.first().map(String::as_str))
+    };
+
+    let mut out = String::new();
+    writeln!(out, "; Disassembled from `{}`.", rom.path.display()).unwrap();
+    writeln!(out, "#include \"lark.customasm\"").unwrap();
+
+    let mut scope = String::new();
+    let define = |out: &mut String, scope: &mut String, addr| {
+        for name in labels.get(&addr).into_iter().flatten() {
+            if !name.contains('.') {
+                *scope = name.clone();
+                writeln!(out, "\n{name}:").unwrap();
+            } else {
+                writeln!(out, "{}:", label_ref(name, scope)).unwrap();
+            }
+        }
+    };
+
+    for item in &rom.items {
+        define(&mut out, &mut scope, item.addr);
+        let lines = match item.kind {
+            ItemKind::Instr(_) => {
+                let (op, mut args) = item.text();
+                if let Some(target) = item.target() {
+                    let target = match target_label(target) {
+                        Some(name) => label_ref(name, &scope),
+                        None => format!("0x{target:04X}"),
+                    };
+                    args = with_target(&args, &target);
+                }
+                vec![format!("{op:<8}{args}")]
+            }
+            ItemKind::Ascii => vec![format!("#d {}", quote(&item.bytes))],
+            ItemKind::Bytes | ItemKind::Zeros => data_directives(&item.bytes),
+        };
+        for (i, text) in lines.iter().enumerate() {
+            let addr = item.addr as usize + i * DATA_PER_LINE;
+            writeln!(out, "    {text:<32} ; 0x{addr:04X}").unwrap();
+        }
+    }
+
+    Ok(out)
+}
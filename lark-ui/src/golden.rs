@@ -0,0 +1,322 @@
+//! Golden-output tests for Lark programs: build a program as `lark build`
+//! would, run it with scripted keyboard input, then compare the VTTY screen
+//! and registers with what's expected.
+//!
+//! A test is a `.larktest` file:
+//!
+//! ```text
+//! # Lines starting with `#` are comments, except in the [vtty] section.
+//! program = hello.lark       # any file `lark build` takes, relative to this file
+//! input = "y\n"              # typed one key at a time
+//! input_interval = 1000      # instructions between keys
+//! max_instrs = 1000000       # stop here if the CPU hasn't halted
+//!
+//! [vtty]
+//! Hello, world!
+//! Continue? y
+//!
+//! [regs]
+//! $rv = 0
+//! ```
+//!
+//! Each line of `[vtty]` is a row of the screen, as the TUI shows it; rows
+//! past the last line are expected to be empty. `[regs]` can name any
+//! register plus `$lo`, `$hi` and `$pc` (in fact any expression `print`
+//! understands).
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use lark_vm::cpu;
+
+use crate::{
+    build,
+    debugger::{self, CmdMsg, Debugger, Output, SourceFiles, StopReason},
+};
+
+const DEFAULT_INPUT_INTERVAL: u64 = 1000;
+const DEFAULT_MAX_INSTRS: u64 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct GoldenTest {
+    /// A source file or ROM, as given to `lark build`.
+    pub program: PathBuf,
+    /// Keys to press, in order.
+    pub input: Vec<u8>,
+    /// Instructions to run before each key press.
+    pub input_interval: u64,
+    pub max_instrs: u64,
+    /// The expected VTTY rows, if the screen is checked.
+    pub vtty: Option<Vec<String>>,
+    /// Expected register values, by name (e.g. `$rv`) or expression.
+    pub regs: Vec<(String, u16)>,
+}
+
+impl GoldenTest {
+    /// Reads a `.larktest` file. Paths in it are relative to the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading `{}`: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&src, dir).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(src: &str, dir: &Path) -> Result<Self, String> {
+        let mut program = None;
+        let mut input = Vec::new();
+        let mut input_interval = DEFAULT_INPUT_INTERVAL;
+        let mut max_instrs = DEFAULT_MAX_INSTRS;
+        let mut vtty = None::<Vec<String>>;
+        let mut regs = Vec::new();
+
+        let mut section = "";
+        for (i, line) in src.lines().enumerate() {
+            let lineno = i + 1;
+            if let Some(name) = line
+                .trim()
+                .strip_prefix('[')
+                .and_then(|l| l.strip_suffix(']'))
+            {
+                section = match name {
+                    "vtty" => {
+                        vtty = Some(Vec::new());
+                        "vtty"
+                    }
+                    "regs" => "regs",
+                    _ => return Err(format!("line {lineno}: Unknown section `[{name}]`")),
+                };
+                continue;
+            }
+
+            if section == "vtty" {
+                vtty.as_mut().unwrap().push(line.to_owned());
+                continue;
+            }
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {lineno}: Expected `<KEY> = <VALUE>`"));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let number = || {
+                debugger::parse_number(value)
+                    .map(u64::from)
+                    .or_else(|| value.parse().ok())
+                    .ok_or_else(|| format!("line {lineno}: Invalid number: `{value}`"))
+            };
+
+            match (section, key) {
+                ("", "program") => program = Some(dir.join(value)),
+                ("", "input") => {
+                    input = unescape(value).map_err(|e| format!("line {lineno}: {e}"))?;
+                }
+                ("", "input_interval") => input_interval = number()?,
+                ("", "max_instrs") => max_instrs = number()?,
+                ("", _) => return Err(format!("line {lineno}: Unknown key `{key}`")),
+                (_, reg) => {
+                    let value = debugger::parse_number(value)
+                        .or_else(|| value.parse::<i16>().ok().map(|v| v as u16))
+                        .ok_or_else(|| format!("line {lineno}: Invalid number: `{value}`"))?;
+                    regs.push((reg.to_owned(), value));
+                }
+            }
+        }
+
+        // Blank lines at the end of the section are just spacing.
+        if let Some(rows) = vtty.as_mut() {
+            while rows.last().is_some_and(|row| row.is_empty()) {
+                rows.pop();
+            }
+            if rows.len() > cpu::VTTY_ROWS {
+                return Err(format!(
+                    "[vtty] has {} rows but the screen only has {}",
+                    rows.len(),
+                    cpu::VTTY_ROWS
+                ));
+            }
+        }
+
+        Ok(Self {
+            program: program.ok_or("No `program` given")?,
+            input,
+            input_interval,
+            max_instrs,
+            vtty,
+            regs,
+        })
+    }
+
+    /// Builds the program into `out_dir` (or next to it) and runs the test,
+    /// returning a report of what didn't match on failure.
+    pub fn run(&self, out_dir: Option<&Path>) -> Result<(), String> {
+        let built = build::build(&self.program, out_dir).map_err(|diags| {
+            diags
+                .iter()
+                .flat_map(|diag| diag.render())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })?;
+        let mut dbg = Debugger::new(SourceFiles::default());
+        if !dbg.load_rom(&built.rom) {
+            return Err(errors(&mut dbg).join("\n"));
+        }
+
+        dbg.run();
+        let mut keys = self.input.iter();
+        while dbg.is_running() && dbg.instrs_retired() < self.max_instrs {
+            let remaining = self.max_instrs - dbg.instrs_retired();
+            match keys.len() {
+                0 => dbg.run_instrs(remaining),
+                _ => {
+                    dbg.run_instrs(self.input_interval.min(remaining));
+                    if let Some(&key) = keys.next() {
+                        dbg.key_event(key);
+                    }
+                }
+            }
+        }
+
+        let mut report = String::new();
+        match dbg.stop_reason() {
+            Some(StopReason::IllegalInstr | StopReason::CpuError) => {
+                for e in errors(&mut dbg) {
+                    writeln!(report, "{e}").unwrap();
+                }
+            }
+            _ if keys.len() > 0 => {
+                writeln!(report, "{} keys of input were never typed", keys.len()).unwrap();
+            }
+            _ => {}
+        }
+
+        if let Some(expected) = &self.vtty {
+            let actual = dbg.vtty_rows();
+            let diff = vtty_diff(expected, &actual);
+            if !diff.is_empty() {
+                writeln!(report, "VTTY mismatch (- expected, + actual):").unwrap();
+                report.push_str(&diff);
+            }
+        }
+
+        for (name, expected) in &self.regs {
+            match dbg.eval(name) {
+                Ok(actual) if actual == *expected => {}
+                Ok(actual) => writeln!(
+                    report,
+                    "{name}: expected 0x{expected:04X} ({expected}), got 0x{actual:04X} ({actual})"
+                )
+                .unwrap(),
+                Err(e) => writeln!(report, "{name}: {e}").unwrap(),
+            }
+        }
+
+        match report.is_empty() {
+            true => Ok(()),
+            false => Err(report.trim_end().to_owned()),
+        }
+    }
+}
+
+/// `lark test`: runs each test file, printing a report for each failure.
+pub fn run_all(paths: &[PathBuf], out_dir: Option<&Path>) -> ExitCode {
+    let mut failed = 0;
+    for path in paths {
+        match GoldenTest::load(path).and_then(|test| test.run(out_dir)) {
+            Ok(()) => println!("PASS {}", path.display()),
+            Err(report) => {
+                failed += 1;
+                println!("FAIL {}", path.display());
+                for line in report.lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+    }
+
+    println!("\n{} passed, {failed} failed", paths.len() - failed);
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn errors(dbg: &mut Debugger) -> Vec<String> {
+    dbg.take_output()
+        .into_iter()
+        .filter_map(|out| match out {
+            Output::Msg(CmdMsg::Error(e)) => Some(e),
+            Output::Msg(CmdMsg::CpuMsg(cpu::LogMsg::Error(e))) => Some(format!("CPU ERROR: {e}")),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Lists the rows that differ, numbered from 0. Missing rows count as empty.
+fn vtty_diff(expected: &[String], actual: &[String]) -> String {
+    let mut diff = String::new();
+    for i in 0..cpu::VTTY_ROWS {
+        let exp = expected.get(i).map_or("", |s| s.as_str());
+        let act = actual.get(i).map_or("", |s| s.as_str());
+        if exp != act {
+            writeln!(diff, "{i:3} - {exp}").unwrap();
+            writeln!(diff, "{i:3} + {act}").unwrap();
+        }
+    }
+    diff
+}
+
+fn strip_comment(line: &str) -> &str {
+    // A `#` inside a quoted `input` string isn't a comment.
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '#' if !in_quotes => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parses a double-quoted string with `\n`, `\r`, `\t`, `\\`, `\"` and
+/// `\xNN` escapes into bytes.
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let Some(inner) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+        return Err(format!("Expected a quoted string: `{s}`"));
+    };
+    let mut out = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('\\') => out.push(b'\\'),
+            Some('"') => out.push(b'"'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                let b = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("Invalid escape: `\\x{hex}`"))?;
+                out.push(b);
+            }
+            Some(c) => return Err(format!("Invalid escape: `\\{c}`")),
+            None => return Err("String ends with a `\\`".to_string()),
+        }
+    }
+    Ok(out)
+}
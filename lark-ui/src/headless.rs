@@ -0,0 +1,108 @@
+//! `lark run --headless`: runs a program without the TUI, for CI.
+//!
+//! Debug output goes to stdout as it happens, and so does the VTTY: after
+//! every chunk of instructions, each row that changed is printed. Chunks are
+//! counted in instructions rather than time, so a program prints the same
+//! thing on every run. Everything the debugger itself says goes to stderr.
+
+use std::{process::ExitCode, time::Instant};
+
+use lark_vm::cpu::LogMsg;
+
+use crate::{
+    build::{self, EXIT_ERROR},
+    cli::RunOpts,
+    debugger::{CmdMsg, Debugger, Output, SourceFiles, StopReason},
+};
+
+/// Instructions to run between looks at the VTTY and the clock.
+const CHUNK_INSTRS: u64 = 10_000;
+
+const EXIT_HALTED: u8 = 0;
+const EXIT_ILLEGAL_INSTR: u8 = 2;
+const EXIT_TIMEOUT: u8 = 3;
+
+pub fn run(opts: &RunOpts) -> ExitCode {
+    let built = match build::build(&opts.build.program, opts.build.out_dir.as_deref()) {
+        Ok(built) => built,
+        Err(diags) => {
+            build::print_diagnostics(&diags);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+
+    let mut dbg = Debugger::new(SourceFiles::default());
+    let loaded = dbg.load_rom(&built.rom);
+    let mut vtty = VttyPrinter::default();
+    print_output(&mut dbg, &mut vtty);
+    if !loaded {
+        return ExitCode::from(EXIT_ERROR);
+    }
+
+    let timeout = opts.timeout;
+    let start = Instant::now();
+    dbg.run();
+
+    while dbg.is_running() {
+        let chunk = match opts.max_instrs {
+            Some(max) => CHUNK_INSTRS.min(max.saturating_sub(dbg.instrs_retired())),
+            None => CHUNK_INSTRS,
+        };
+        if chunk == 0 {
+            print_output(&mut dbg, &mut vtty);
+            eprintln!("Stopped after {} instructions.", opts.max_instrs.unwrap());
+            return ExitCode::from(EXIT_TIMEOUT);
+        }
+        if start.elapsed() >= timeout {
+            print_output(&mut dbg, &mut vtty);
+            eprintln!("Timed out after {:?}.", timeout);
+            return ExitCode::from(EXIT_TIMEOUT);
+        }
+
+        dbg.run_instrs(chunk);
+        print_output(&mut dbg, &mut vtty);
+    }
+
+    ExitCode::from(match dbg.stop_reason() {
+        Some(StopReason::Halt) => EXIT_HALTED,
+        Some(StopReason::IllegalInstr) => EXIT_ILLEGAL_INSTR,
+        Some(StopReason::Breakpoint | StopReason::CpuError) | None => EXIT_ERROR,
+    })
+}
+
+fn print_output(dbg: &mut Debugger, vtty: &mut VttyPrinter) {
+    vtty.print_changes(dbg.state().vtty_rows());
+
+    for out in dbg.take_output() {
+        let Output::Msg(msg) = out else {
+            continue;
+        };
+        match msg {
+            CmdMsg::CpuMsg(LogMsg::DebugPuts { value, .. }) => println!("{value}"),
+            CmdMsg::CpuMsg(LogMsg::Error(e)) => eprintln!("CPU ERROR: {e}"),
+            CmdMsg::CpuMsg(_) => {}
+            CmdMsg::Log(s) | CmdMsg::Info(s) | CmdMsg::Command(s) => eprintln!("{s}"),
+            CmdMsg::Error(s) => eprintln!("error: {s}"),
+        }
+    }
+}
+
+/// Remembers what each VTTY row said when it was last printed.
+#[derive(Default)]
+struct VttyPrinter {
+    rows: Vec<String>,
+}
+
+impl VttyPrinter {
+    fn print_changes(&mut self, rows: Vec<String>) {
+        self.rows.resize(rows.len(), String::new());
+        for (text, printed) in rows.into_iter().zip(&mut self.rows) {
+            if text != *printed {
+                if !text.is_empty() {
+                    println!("{text}");
+                }
+                *printed = text;
+            }
+        }
+    }
+}
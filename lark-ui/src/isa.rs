@@ -0,0 +1,252 @@
+//! Instruction encodings from `lark.customasm`, for the parts of the UI that
+//! need to look inside an instruction without executing it.
+//!
+//! Instructions are packed MSB-first: a 6-bit opcode followed by the operand
+//! fields, so the opcode is always the top six bits of the first byte.
+
+pub const HALT: u8 = 0x01;
+pub const J: u8 = 0x08;
+pub const JR: u8 = 0x09;
+pub const JAL: u8 = 0x0A;
+pub const JRAL: u8 = 0x0B;
+pub const BT: u8 = 0x0C;
+pub const KRET: u8 = 0x0D;
+pub const BF: u8 = 0x0F;
+pub const LW: u8 = 0x11;
+pub const LBS: u8 = 0x12;
+pub const LBU: u8 = 0x13;
+pub const SW: u8 = 0x15;
+pub const SB: u8 = 0x16;
+pub const ADDI: u8 = 0x28;
+pub const SUBI: u8 = 0x29;
+
+pub fn opcode(first_byte: u8) -> u8 {
+    first_byte >> 2
+}
+
+/// How an instruction's operands are written and laid out after the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// `halt`
+    None,
+    /// `j LABEL`, stored as a 16-bit offset from the instruction.
+    Jump,
+    /// `jal $ra, LABEL` or `bt $cond, LABEL`.
+    RegJump,
+    /// `exn CODE` with a 10-bit unsigned code.
+    Code,
+    /// `jr $dst`
+    Reg,
+    /// `mv $dst, $src`
+    RegReg,
+    /// `li $dst, IMM16`
+    RegImm16,
+    /// `add $dst, $rs1, $rs2`
+    RegRegReg,
+    /// `addi $dst, $src, IMM10`
+    RegRegImm10,
+    /// `mul $rs1, $rs2`
+    MulDiv,
+    /// `sw OFF($base), $src`
+    Store,
+    /// `lw $dst, OFF($base)`
+    Load,
+}
+
+impl Form {
+    /// Encoded size in bytes.
+    pub fn size(self) -> u16 {
+        match self {
+            Form::None => 1,
+            Form::Code | Form::Reg | Form::RegReg => 2,
+            Form::Jump
+            | Form::RegRegReg
+            | Form::RegRegImm10
+            | Form::MulDiv
+            | Form::Store
+            | Form::Load => 3,
+            Form::RegJump | Form::RegImm16 => 4,
+        }
+    }
+}
+
+/// Every instruction `lark.customasm` has a rule for. (`jral` has an opcode
+/// but no rule.)
+pub const INSTRS: &[(&str, u8, Form)] = &[
+    ("halt", HALT, Form::None),
+    ("nop", 0x02, Form::None),
+    ("kret", KRET, Form::None),
+    ("inrd", 0x1D, Form::None),
+    ("inre", 0x1C, Form::None),
+    ("j", J, Form::Jump),
+    ("jal", JAL, Form::RegJump),
+    ("bt", BT, Form::RegJump),
+    ("bf", BF, Form::RegJump),
+    ("exn", 0x00, Form::Code),
+    ("kcall", 0x0E, Form::Code),
+    ("jr", JR, Form::Reg),
+    ("mvlo", 0x2A, Form::Reg),
+    ("mvhi", 0x2B, Form::Reg),
+    ("mv", 0x14, Form::RegReg),
+    ("not", 0x27, Form::RegReg),
+    ("neg", 0x2F, Form::RegReg),
+    ("seb", 0x37, Form::RegReg),
+    ("li", 0x10, Form::RegImm16),
+    ("or", 0x24, Form::RegRegReg),
+    ("xor", 0x25, Form::RegRegReg),
+    ("and", 0x26, Form::RegRegReg),
+    ("add", 0x20, Form::RegRegReg),
+    ("addu", 0x30, Form::RegRegReg),
+    ("sub", 0x21, Form::RegRegReg),
+    ("subu", 0x31, Form::RegRegReg),
+    ("shl", 0x34, Form::RegRegReg),
+    ("shr", 0x35, Form::RegRegReg),
+    ("shra", 0x36, Form::RegRegReg),
+    ("tlt", 0x38, Form::RegRegReg),
+    ("tge", 0x39, Form::RegRegReg),
+    ("teq", 0x3A, Form::RegRegReg),
+    ("tne", 0x3B, Form::RegRegReg),
+    ("tltu", 0x3C, Form::RegRegReg),
+    ("tgeu", 0x3D, Form::RegRegReg),
+    ("tez", 0x3E, Form::RegRegReg),
+    ("tnz", 0x3F, Form::RegRegReg),
+    ("ori", 0x2C, Form::RegRegImm10),
+    ("xori", 0x2D, Form::RegRegImm10),
+    ("andi", 0x2E, Form::RegRegImm10),
+    ("addi", ADDI, Form::RegRegImm10),
+    ("subi", SUBI, Form::RegRegImm10),
+    ("mul", 0x22, Form::MulDiv),
+    ("mulu", 0x32, Form::MulDiv),
+    ("div", 0x23, Form::MulDiv),
+    ("divu", 0x33, Form::MulDiv),
+    ("sw", SW, Form::Store),
+    ("sb", SB, Form::Store),
+    ("lw", LW, Form::Load),
+    ("lbs", LBS, Form::Load),
+    ("lbu", LBU, Form::Load),
+];
+
+/// The mnemonic and form of the instruction with `opcode`.
+pub fn lookup(opcode: u8) -> Option<(&'static str, Form)> {
+    INSTRS
+        .iter()
+        .find(|&&(_, op, _)| op == opcode)
+        .map(|&(name, _, form)| (name, form))
+}
+
+/// Register names by number, as the `Reg` subrule spells them.
+pub const REG_NAMES: [&str; 16] = [
+    "$zero", "$rv", "$ra", "$a0", "$a1", "$a2", "$s0", "$s1", "$s2", "$t0", "$t1", "$t2", "$k0",
+    "$k1", "$gp", "$sp",
+];
+
+pub fn reg_number(name: &str) -> Option<u8> {
+    REG_NAMES.iter().position(|&r| r == name).map(|i| i as u8)
+}
+
+/// Register number of the return value register, `$rv`.
+pub const RV: u8 = 0x1;
+/// Register number of the return address register, `$ra`.
+pub const RA: u8 = 0x2;
+/// Register number of the stack pointer, `$sp`.
+pub const SP: u8 = 0xF;
+
+/// Register number of the first register operand, which sits right after the
+/// opcode.
+pub fn first_reg(bytes: [u8; 2]) -> u8 {
+    ((bytes[0] & 0b11) << 2) | (bytes[1] >> 6)
+}
+
+/// Where the jump or branch starting with `bytes` at `addr` goes, if it is
+/// one. Targets are stored as a 16-bit offset from the instruction: `j` is
+/// laid out `op off16` and the others `op reg off16`.
+pub fn jump_target(bytes: [u8; 4], addr: u16) -> Option<u16> {
+    let offset = match opcode(bytes[0]) {
+        J => u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 2,
+        JAL | BT | BF => u32::from_be_bytes(bytes) >> 6,
+        _ => return None,
+    };
+    Some(addr.wrapping_add(offset as u16))
+}
+
+/// How far `addi $sp, $sp, IMM` or `subi $sp, $sp, IMM` starting with
+/// `bytes` moves `$sp`, if it is one. These are laid out `op dst src imm10`.
+pub fn sp_adjustment(bytes: [u8; 3]) -> Option<i16> {
+    let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+    let (dst, src) = ((bits >> 14) & 0xF, (bits >> 10) & 0xF);
+    if dst != SP as u32 || src != SP as u32 {
+        return None;
+    }
+    let imm = sign_extend(bits & 0x3FF, 10);
+    match opcode(bytes[0]) {
+        ADDI => Some(imm),
+        SUBI => Some(imm.wrapping_neg()),
+        _ => None,
+    }
+}
+
+/// The register number a `sw` starting with `bytes` stores, if it is one.
+pub fn stored_reg(bytes: [u8; 3]) -> Option<u8> {
+    match opcode(bytes[0]) {
+        SW => Some((bytes[1] >> 2) & 0xF),
+        _ => None,
+    }
+}
+
+/// Sign-extends the low `bits` bits of `x`.
+pub fn sign_extend(x: u32, bits: u32) -> i16 {
+    ((x << (32 - bits)) as i32 >> (32 - bits)) as i16
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAccessKind {
+    Read,
+    Write,
+}
+
+/// The `offset(base)` operand of a load or store.
+pub struct MemOperand {
+    pub kind: MemAccessKind,
+    pub width: u16,
+    /// Register number of the base register.
+    pub base: u8,
+    pub offset: i16,
+}
+
+impl MemOperand {
+    pub fn resolve(&self, base_value: u16) -> MemAccess {
+        MemAccess {
+            addr: base_value.wrapping_add_signed(self.offset),
+            len: self.width,
+            kind: self.kind,
+        }
+    }
+}
+
+/// A range of memory an instruction reads or writes.
+#[derive(Debug, Clone, Copy)]
+pub struct MemAccess {
+    pub addr: u16,
+    pub len: u16,
+    pub kind: MemAccessKind,
+}
+
+/// Decodes the memory operand of the instruction starting with `bytes`, if it
+/// is a load or store. Loads are laid out `op dst base off10` and stores are
+/// `op base src off10`.
+pub fn mem_operand(bytes: [u8; 3]) -> Option<MemOperand> {
+    let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+    let (kind, width, base) = match opcode(bytes[0]) {
+        LW => (MemAccessKind::Read, 2, bits >> 10),
+        LBS | LBU => (MemAccessKind::Read, 1, bits >> 10),
+        SW => (MemAccessKind::Write, 2, bits >> 14),
+        SB => (MemAccessKind::Write, 1, bits >> 14),
+        _ => return None,
+    };
+    Some(MemOperand {
+        kind,
+        width,
+        base: (base & 0xF) as u8,
+        offset: sign_extend(bits & 0x3FF, 10),
+    })
+}
@@ -0,0 +1,13 @@
+//! The LARK debugger and TUI. The binary is a thin wrapper around this; it's a
+//! library so that tools can run programs and tests without a terminal.
+
+pub mod asm;
+pub mod build;
+pub mod cli;
+pub mod debugger;
+pub mod diagnostic;
+pub mod disasm;
+pub mod golden;
+pub mod headless;
+mod isa;
+pub mod tui;
@@ -1,10 +1,54 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 
-mod cli;
-mod tui;
+use lark_ui::{
+    build,
+    cli::{self, BuildOpts, Command},
+    debugger::SourceFiles,
+    disasm, golden, headless, tui,
+};
 
-fn main() {
+fn main() -> ExitCode {
     let opts = cli::Opts::parse();
 
-    tui::App::new(opts).run().expect("Failed to initialize TUI");
+    let (files, run) = match &opts.command {
+        None => (
+            SourceFiles {
+                meadowlark_src: opts.meadowlark_src.clone(),
+                lark_src: opts.lark_src.clone(),
+                romfile: opts.romfile.clone(),
+            },
+            false,
+        ),
+        Some(Command::Build(build)) => return build::run(build),
+        Some(Command::Disasm(disasm)) => return disasm::run(disasm),
+        Some(Command::Test { tests, out_dir }) => {
+            return golden::run_all(tests, out_dir.as_deref())
+        }
+        Some(Command::Run(run)) if run.headless => return headless::run(run),
+        Some(Command::Run(run)) => match build_for_tui(&run.build) {
+            Ok(files) => (files, true),
+            Err(code) => return code,
+        },
+        Some(Command::Debug(build)) => match build_for_tui(build) {
+            Ok(files) => (files, false),
+            Err(code) => return code,
+        },
+    };
+
+    tui::App::new(files, opts.clock_hz, run)
+        .run()
+        .expect("Failed to initialize TUI");
+    ExitCode::SUCCESS
+}
+
+fn build_for_tui(opts: &BuildOpts) -> Result<SourceFiles, ExitCode> {
+    match build::build(&opts.program, opts.out_dir.as_deref()) {
+        Ok(built) => Ok(built.files),
+        Err(diags) => {
+            build::print_diagnostics(&diags);
+            Err(ExitCode::from(build::EXIT_ERROR))
+        }
+    }
 }
@@ -1,39 +1,47 @@
-use std::{
-    cell::RefCell,
-    path::PathBuf,
-    rc::Rc,
-    sync::mpsc::{Receiver, Sender},
-    time::{Duration, Instant},
-};
+use std::path::PathBuf;
 
 use anyhow::Result;
 use crossterm::event::MouseEvent;
+use lark_vm::{cpu::regs::Reg, utils::s16};
 use ratatui::prelude::*;
 
-use lark_vm::cpu::{self, instr::Instr, Cpu, MemBlock};
-use tui_scrollview::ScrollViewState;
-
-use crate::cli::Opts;
+use crate::{
+    debugger::{CmdMsg, LineTable, MachineState, SourceFiles, Symbols},
+    disasm::Item,
+};
 
-use self::ui::CmdMsg;
+use self::{
+    ui::{DisassemblyLayout, DisassemblyState, MemoryState},
+    worker::{Request, Response, Worker},
+};
 
 mod ui;
 mod update;
 mod utils;
+mod worker;
 
 // App state
 pub struct App {
-    cpu: Cpu,
+    worker: Worker,
+    /// The machine as of the worker's last report.
+    machine: MachineState,
+    /// The registers when the CPU last stopped, and the time before that,
+    /// which the registers pane highlights changes since.
+    last_stop: Option<RegSnapshot>,
+    prev_stop: Option<RegSnapshot>,
+    /// Show what changed registers used to hold.
+    reg_delta: bool,
     meadowlark_src: Option<PathBuf>,
     lark_src: Option<PathBuf>,
     romfile: Option<PathBuf>,
-    vtty_buf: Rc<RefCell<MemBlock<{ cpu::VTTY_BYTES }>>>,
 
-    disassembly: Vec<Instr>,
+    disassembly: Vec<Item>,
+    dis_layout: DisassemblyLayout,
+    symbols: Symbols,
+    lines: LineTable,
+    /// The text of each of `lines.files()`, by line.
+    sources: Vec<Vec<String>>,
 
-    cpu_signal_channel: Receiver<lark_vm::cpu::Signal>,
-    cpu_interrupt_channel: Sender<lark_vm::cpu::interrupts::Interrupt>,
-    cpu_run_till_breakpoint: bool,
     /// The command currently being typed.
     cmd_input: tui_input::Input,
     cmd_input_focus: bool,
@@ -42,39 +50,63 @@ pub struct App {
     cmd_history: Vec<String>,
     cmd_history_idx: usize,
 
-    instr_stopwatch_start: Instant,
-    instr_time_delta: Option<Duration>,
-
     mouse_click: Option<MouseEvent>,
     tab_idx: usize,
-    disassembly_scroll_view_state: ScrollViewState,
+    dis_state: DisassemblyState,
+    mem_state: MemoryState,
 
     should_quit: bool,
 }
 
 impl App {
-    pub fn new(opts: Opts) -> Self {
-        let vtty_buf = Rc::new(RefCell::new(MemBlock::new_zeroed()));
+    /// Opens `files`, or the last session's if there are none, and starts
+    /// running straight away if `run` is set.
+    pub fn new(files: SourceFiles, clock_hz: Option<u32>, run: bool) -> Self {
         let cmd_history = Self::load_histfile();
-        let (tx, rx) = std::sync::mpsc::channel();
-        let (interrupt_tx, interrupt_rx) = std::sync::mpsc::channel();
+        let session = Self::load_session();
 
-        let cpu = Cpu::new(Default::default(), vtty_buf.clone(), tx, interrupt_rx);
+        let files = match files {
+            SourceFiles {
+                meadowlark_src: None,
+                lark_src: None,
+                romfile: None,
+            } => SourceFiles {
+                meadowlark_src: session.meadowlark_src,
+                lark_src: session.lark_src,
+                romfile: session.romfile,
+            },
+            files => files,
+        };
+        let worker = Worker::spawn(files.clone(), clock_hz);
+        if run {
+            worker.send(Request::Run);
+        }
 
-        let session = Self::load_session();
+        // The worker reports the initial state before anything else, after
+        // loading the ROM (if any).
+        let mut responses = Vec::new();
+        let machine = loop {
+            match worker.recv() {
+                Response::State(state) => break *state,
+                resp => responses.push(resp),
+            }
+        };
 
         let mut app = Self {
-            cpu,
-            meadowlark_src: opts.meadowlark_src.or(session.meadowlark_src),
-            lark_src: opts.lark_src.or(session.lark_src),
-            romfile: opts.romfile.or(session.romfile),
-            vtty_buf,
+            worker,
+            last_stop: Some(RegSnapshot::of(&machine)),
+            prev_stop: None,
+            reg_delta: session.reg_delta,
+            machine,
+            meadowlark_src: files.meadowlark_src,
+            lark_src: files.lark_src,
+            romfile: files.romfile,
 
             disassembly: Vec::new(),
-
-            cpu_signal_channel: rx,
-            cpu_interrupt_channel: interrupt_tx,
-            cpu_run_till_breakpoint: false,
+            dis_layout: DisassemblyLayout::default(),
+            symbols: Symbols::default(),
+            lines: LineTable::default(),
+            sources: Vec::new(),
 
             cmd_input: tui_input::Input::default(),
             cmd_input_focus: true,
@@ -83,18 +115,16 @@ impl App {
             cmd_history,
             cmd_history_idx: 0,
 
-            instr_stopwatch_start: Instant::now(),
-            instr_time_delta: None,
-
             mouse_click: None,
             tab_idx: session.tab_idx,
-            disassembly_scroll_view_state: ScrollViewState::default(),
+            dis_state: DisassemblyState::default(),
+            mem_state: MemoryState::default(),
 
             should_quit: false,
         };
 
-        if let Some(romfile) = app.romfile.as_ref() {
-            app.load_rom(&romfile.to_owned());
+        for resp in responses {
+            app.handle_response(resp);
         }
 
         app
@@ -192,11 +222,55 @@ impl App {
     }
 }
 
+/// The registers at one point, to compare against later.
+struct RegSnapshot {
+    regs: Vec<(Reg, s16)>,
+    lo: s16,
+    hi: s16,
+    pc: u16,
+    instrs_retired: u64,
+}
+
+impl RegSnapshot {
+    fn of(machine: &MachineState) -> Self {
+        Self {
+            regs: machine.regs.clone(),
+            lo: machine.lo,
+            hi: machine.hi,
+            pc: machine.pc,
+            instrs_retired: machine.instrs_retired,
+        }
+    }
+
+    /// Whether `machine` is where this was taken, with the same registers.
+    fn matches(&self, machine: &MachineState) -> bool {
+        let same = |a: s16, b: s16| a.as_u16() == b.as_u16();
+        self.instrs_retired == machine.instrs_retired
+            && self.pc == machine.pc
+            && same(self.lo, machine.lo)
+            && same(self.hi, machine.hi)
+            && self.regs.len() == machine.regs.len()
+            && self
+                .regs
+                .iter()
+                .zip(&machine.regs)
+                .all(|(&(r1, v1), &(r2, v2))| r1 == r2 && same(v1, v2))
+    }
+
+    fn reg(&self, reg: Reg) -> Option<u16> {
+        self.regs
+            .iter()
+            .find(|(r, _)| *r == reg)
+            .map(|(_, value)| value.as_u16())
+    }
+}
+
 struct Session {
     meadowlark_src: Option<PathBuf>,
     lark_src: Option<PathBuf>,
     romfile: Option<PathBuf>,
     tab_idx: usize,
+    reg_delta: bool,
 }
 
 impl Session {
@@ -218,6 +292,7 @@ impl Session {
         }
 
         writeln!(s, "tab_idx = {}", self.tab_idx).unwrap();
+        writeln!(s, "reg_delta = {}", self.reg_delta).unwrap();
 
         s
     }
@@ -227,6 +302,7 @@ impl Session {
         let mut lark_src = None;
         let mut romfile = None;
         let mut tab_idx = 0;
+        let mut reg_delta = false;
 
         for line in s.lines() {
             let (key, value) = line.split_once(" = ").unwrap();
@@ -236,6 +312,7 @@ impl Session {
                 "lark_src" => lark_src = Some(PathBuf::from(value)),
                 "romfile" => romfile = Some(PathBuf::from(value)),
                 "tab_idx" => tab_idx = value.parse().unwrap_or_default(),
+                "reg_delta" => reg_delta = value.parse().unwrap_or_default(),
                 _ => {}
             }
         }
@@ -245,6 +322,7 @@ impl Session {
             lark_src,
             romfile,
             tab_idx,
+            reg_delta,
         }
     }
 }
@@ -285,6 +363,7 @@ impl Drop for App {
             lark_src: self.lark_src.take(),
             romfile: self.romfile.take(),
             tab_idx: self.tab_idx,
+            reg_delta: self.reg_delta,
         };
 
         Self::save_session(&session);
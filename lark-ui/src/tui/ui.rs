@@ -1,9 +1,22 @@
 use lark_vm::cpu::{self, ArgStyle};
 use ratatui::{prelude::*, style::Styled, widgets::*};
 
+use crate::debugger::CmdMsg;
+
 use super::{utils, App};
 
+pub(super) use self::{
+    dis::{DisassemblyLayout, DisassemblyState},
+    mem::MemoryState,
+};
+
 mod dis;
+mod mem;
+mod source;
+
+const TABS: [&str; 4] = ["VTTY", "Disassembly", "Source", "Memory"];
+pub(super) const DISASSEMBLY_TAB: usize = 1;
+pub(super) const MEMORY_TAB: usize = 3;
 
 impl App {
     pub fn ui(&mut self, f: &mut Frame) {
@@ -52,7 +65,7 @@ impl App {
             ])
             .split(row);
 
-        let tabs = Tabs::new(vec!["VTTY", "Disassembly"])
+        let tabs = Tabs::new(TABS.to_vec())
             .select(self.tab_idx)
             .style(Style::default().fg(Color::Yellow))
             .highlight_style(Style::default().reversed());
@@ -62,7 +75,7 @@ impl App {
             let (top, bot, left, right) = (rect.top(), rect.bottom(), rect.left(), rect.right());
             let (x, y) = (m.column, m.row);
             if top <= y && y < bot && left <= x && x < right {
-                self.tab_idx = 1 - self.tab_idx;
+                self.tab_idx = (self.tab_idx + 1) % TABS.len();
                 self.mouse_click = None;
             }
         }
@@ -72,23 +85,42 @@ impl App {
         let content_layout = layout[1];
         match self.tab_idx {
             0 => self.render_vtty(f, content_layout),
-            1 => {
-                let block = Block::default().borders(Borders::ALL).title("Disassembly");
+            DISASSEMBLY_TAB => {
+                let block = Block::default().borders(Borders::ALL).title(
+                    "Disassembly  S-Up/Down: select  S-Right: follow  S-Left: back  F9: break",
+                );
                 let inner_content_layout = block.inner(content_layout);
 
                 f.render_widget(block, content_layout);
 
+                let click = self
+                    .mouse_click
+                    .map(|m| Position::new(m.column, m.row))
+                    .filter(|&pos| inner_content_layout.contains(pos));
+                if click.is_some() {
+                    self.mouse_click = None;
+                }
                 let disassembly_view = dis::DisassemblyView {
                     disassembly: &self.disassembly,
-                    pc: self.cpu.pc,
+                    layout: &self.dis_layout,
+                    symbols: &self.symbols,
+                    pc: self.machine.pc,
+                    running: self.machine.running,
+                    breakpoints: &self.machine.breakpoints,
+                    click,
                 };
 
                 f.render_stateful_widget(
                     disassembly_view,
                     inner_content_layout,
-                    &mut self.disassembly_scroll_view_state,
+                    &mut self.dis_state,
                 );
+                if let Some(addr) = self.dis_state.take_toggled() {
+                    self.toggle_breakpoint(addr);
+                }
             }
+            2 => self.render_source(f, content_layout),
+            MEMORY_TAB => self.render_memory(f, content_layout),
             _ => unreachable!(),
         }
     }
@@ -99,22 +131,44 @@ impl App {
         // Split into lower and upper sections, upper section is for registers, lower is smaller and is for info:
         let layout = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([Constraint::Length(15 + 2 + 1 + 2), Constraint::Min(3)])
+            .constraints([
+                Constraint::Length(15 + 2 + 1 + 2),
+                Constraint::Length(3 + 2),
+                Constraint::Min(3),
+            ])
             .split(side_panel);
 
         let regs_pane = layout[0];
         let info_pane = layout[1];
+        let stack_pane = layout[2];
 
         self.render_registers(f, regs_pane);
         self.render_info_pane(f, info_pane);
+        self.render_stack(f, stack_pane);
     }
 
+    /// Registers colored by role, with the ones that changed since the CPU
+    /// last stopped highlighted (and with `reg-delta on`, their old values).
     fn render_registers(&self, f: &mut Frame<'_>, side_panel: Rect) {
+        use std::fmt::Write;
+
+        let prev = self.prev_stop.as_ref().filter(|_| !self.machine.running);
+        let changed = |old: Option<u16>, new: u16| old.filter(|&old| old != new);
+        let delta = |text: &mut String, old: Option<u16>| {
+            if let Some(old) = old.filter(|_| self.reg_delta) {
+                write!(text, "  was 0x{old:04X}").unwrap();
+            }
+        };
+        let highlight = |style: Style, old: Option<u16>| match old {
+            Some(_) => style.bold().reversed(),
+            None => style,
+        };
+
         let reg_lines = self
-            .cpu
+            .machine
             .regs
             .iter()
-            .map(|(reg, value)| {
+            .map(|&(reg, value)| {
                 let color = if reg.is_ret_related() {
                     Color::Yellow
                 } else if reg.is_argument() {
@@ -128,9 +182,9 @@ impl App {
                 } else {
                     Color::White
                 };
-                let style = Style::new().fg(color);
+                let old = changed(prev.and_then(|p| p.reg(reg)), value.as_u16());
+                let style = highlight(Style::new().fg(color), old);
                 let idx = reg as u8;
-                use std::fmt::Write;
                 let mut text = String::with_capacity(30);
                 write!(text, "${:<2}", idx).unwrap();
                 write!(text, " {reg}:").unwrap();
@@ -143,26 +197,38 @@ impl App {
                     }
                     _ => {}
                 };
+                delta(&mut text, old);
                 ListItem::new(Line::styled(text, style))
             })
             .chain([
                 {
-                    let unsigned = self.cpu.lo.as_u16();
-                    let signed = self.cpu.lo.as_i16();
-                    ListItem::new(Line::raw(format!(
+                    let unsigned = self.machine.lo.as_u16();
+                    let signed = self.machine.lo.as_i16();
+                    let old = changed(prev.map(|p| p.lo.as_u16()), unsigned);
+                    let mut text = format!(
                         "    $LO: 0x{:04X}, {:5}u, {:+5}",
                         unsigned, unsigned, signed
-                    )))
+                    );
+                    delta(&mut text, old);
+                    ListItem::new(Line::styled(text, highlight(Style::new(), old)))
                 },
                 {
-                    let unsigned = self.cpu.hi.as_u16();
-                    let signed = self.cpu.hi.as_i16();
-                    ListItem::new(Line::raw(format!(
+                    let unsigned = self.machine.hi.as_u16();
+                    let signed = self.machine.hi.as_i16();
+                    let old = changed(prev.map(|p| p.hi.as_u16()), unsigned);
+                    let mut text = format!(
                         "    $HI: 0x{:04X}, {:5}u, {:+5}",
                         unsigned, unsigned, signed
-                    )))
+                    );
+                    delta(&mut text, old);
+                    ListItem::new(Line::styled(text, highlight(Style::new(), old)))
+                },
+                {
+                    let old = changed(prev.map(|p| p.pc), self.machine.pc);
+                    let mut text = format!("    $pc: 0x{:04X}", self.machine.pc);
+                    delta(&mut text, old);
+                    ListItem::new(Line::styled(text, highlight(Style::new(), old)))
                 },
-                ListItem::new(Line::raw(format!("    $pc: 0x{:04X}", self.cpu.pc))),
             ]);
 
         f.render_widget(
@@ -172,22 +238,49 @@ impl App {
     }
 
     fn render_info_pane(&self, f: &mut Frame<'_>, side_panel: Rect) {
-        let instr_per_sec = if let Some(duration) = self.instr_time_delta {
-            if duration.as_secs_f32() > 0.0 {
-                1.0f32 / duration.as_secs_f32()
-            } else {
-                0.0
-            }
-        } else {
-            0.0
+        let instr_per_sec = self.machine.instrs_per_sec.unwrap_or(0.0);
+        let target = match self.machine.clock_hz {
+            Some(hz) => hz.to_string(),
+            None => "max".to_string(),
         };
-        let text = vec![Line::raw(format!("instr/sec: {instr_per_sec:.0}"))];
+        let text = vec![
+            Line::raw(format!("target:    {target}")),
+            Line::raw(format!("instr/sec: {instr_per_sec:.0}")),
+            Line::raw(format!("retired:   {}", self.machine.instrs_retired)),
+        ];
         f.render_widget(
             List::new(text).block(Block::default().borders(Borders::ALL).title("Info")),
             side_panel,
         );
     }
 
+    /// The words around `$sp`, marking saved registers and return addresses.
+    fn render_stack(&self, f: &mut Frame<'_>, side_panel: Rect) {
+        let lines = self.machine.stack.iter().map(|word| {
+            let marker = match word.offset {
+                0 => "$sp>".bold(),
+                _ => Span::raw("    "),
+            };
+            let text = format!(
+                " {:+4} 0x{:04X}: 0x{:04X}",
+                word.offset, word.addr, word.value
+            );
+            let text = match word.offset < 0 {
+                true => text.dark_gray(),
+                false => text.into(),
+            };
+            let mut spans = vec![marker, text];
+            if let Some(note) = &word.note {
+                spans.push(format!("  {note}").cyan());
+            }
+            ListItem::new(Line::from(spans))
+        });
+        f.render_widget(
+            List::new(lines).block(Block::default().borders(Borders::ALL).title("Stack")),
+            side_panel,
+        );
+    }
+
     fn render_cmd_input(&self, cmd_input_row: Rect, f: &mut Frame<'_>) {
         if self.cmd_input_focus {
             let width = cmd_input_row.width.max(3) - 3;
@@ -295,10 +388,12 @@ impl App {
     fn render_cmd_output(&self, f: &mut Frame<'_>, cmd_output_row: Rect) {
         let mut list_items = Vec::<ListItem>::new();
 
+        // The list is drawn bottom to top, so go through the messages newest
+        // first and through each message's lines last to first.
         for msg in self.cmd_output.iter().rev() {
             match msg {
                 CmdMsg::Error(lines) => {
-                    for line in lines.lines() {
+                    for line in lines.lines().rev() {
                         list_items.push(ListItem::new(Line {
                             spans: vec![
                                 Span::styled(
@@ -312,7 +407,7 @@ impl App {
                     }
                 }
                 CmdMsg::Info(lines) => {
-                    for line in lines.lines() {
+                    for line in lines.lines().rev() {
                         list_items.push(ListItem::new(Line {
                             spans: vec![
                                 Span::styled(
@@ -328,7 +423,7 @@ impl App {
                     }
                 }
                 CmdMsg::Log(lines) => {
-                    for line in lines.lines() {
+                    for line in lines.lines().rev() {
                         list_items.push(ListItem::new(Line {
                             spans: vec![
                                 Span::styled(
@@ -344,7 +439,7 @@ impl App {
                     }
                 }
                 CmdMsg::Command(lines) => {
-                    for line in lines.lines() {
+                    for line in lines.lines().rev() {
                         list_items.push(ListItem::new(Line {
                             spans: vec![
                                 Span::styled("> ", Style::default().italic()),
@@ -373,22 +468,49 @@ impl App {
         );
     }
 
+    /// The source file the current `pc` came from, per the line table.
+    fn render_source(&self, f: &mut Frame, row: Rect) {
+        let loc = self.lines.line_at(self.machine.pc);
+        let file = loc.map_or(0, |loc| loc.file);
+        let (Some(path), Some(text)) = (self.lines.files().get(file), self.sources.get(file))
+        else {
+            let msg = Paragraph::new("No line table is loaded for this ROM.")
+                .block(Block::default().borders(Borders::ALL).title("Source"));
+            f.render_widget(msg, row);
+            return;
+        };
+
+        let view = source::SourceView {
+            path,
+            text,
+            current_line: loc.map(|loc| loc.line),
+        };
+        f.render_widget(view, row);
+    }
+
+    /// The memory window, with the `mem follow` expression or the edit in
+    /// progress in the title.
+    fn render_memory(&mut self, f: &mut Frame, row: Rect) {
+        let window = &self.machine.memory;
+        let mut title = "Memory  S-arrows: move  F2: edit byte  F3: edit word".to_owned();
+        if let Some(edit) = self.mem_state.describe_edit(window) {
+            title = format!("Memory  Editing {edit}");
+        } else if let Some((src, addr)) = &window.follow {
+            match addr {
+                Some(addr) => title += &format!("  Following `{src}` = 0x{addr:04X}"),
+                None => title += &format!("  Following `{src}` (can't evaluate)"),
+            }
+        }
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(row);
+        f.render_widget(block, row);
+        f.render_stateful_widget(mem::MemoryView { window }, inner, &mut self.mem_state);
+    }
+
     fn render_vtty(&self, f: &mut Frame, row: Rect) {
         let mut lines = Vec::<Line>::new();
 
-        // Interpret vtty buffer as a 2D array of rows of text with maximum 80
-        // bytes each.
-        for row in 0..cpu::VTTY_ROWS {
-            let buf = self.vtty_buf.borrow();
-            let row = {
-                let row_start = row * cpu::VTTY_COLS;
-                let row_end = (row + 1) * cpu::VTTY_COLS;
-                &buf.mem[row_start..row_end]
-            };
-            // Trim everything after the first 0 byte.
-            let row_end = row.iter().position(|&b| b == 0).unwrap_or(0);
-            // Convert to String, dropping any non-UTF-8 bytes.
-            let s = String::from_utf8_lossy(&row[..row_end]).to_string();
+        for s in self.machine.vtty_rows() {
             lines.push(Line::raw(s));
         }
 
@@ -406,11 +528,3 @@ impl App {
         );
     }
 }
-
-pub enum CmdMsg {
-    Log(String),
-    Info(String),
-    Error(String),
-    Command(String),
-    CpuMsg(cpu::LogMsg),
-}
@@ -1,55 +1,440 @@
-use lark_vm::{
-    cpu::{instr::Instr, regs::Reg},
-    utils::s16,
+use std::collections::HashMap;
+
+use ratatui::{
+    layout::{Position, Size},
+    prelude::*,
+    widgets::*,
 };
-use ratatui::{layout::Size, prelude::*, widgets::*};
 use tui_scrollview::{ScrollView, ScrollViewState};
 
+use crate::{debugger::Symbols, disasm::Item};
+
+/// At most this many arrows run side by side in the gutter; any more aren't
+/// drawn.
+const MAX_LANES: usize = 6;
+
+/// How many jumps to an address to list before just counting the rest.
+const MAX_XREFS: usize = 3;
+
+/// Width of the column of breakpoint and `pc` markers.
+const MARKERS_WIDTH: u16 = 2;
+
+/// The selected row and where following branches came from, which outlive a
+/// frame.
+#[derive(Default)]
+pub struct DisassemblyState {
+    pub scroll: ScrollViewState,
+    /// Index into the disassembly.
+    selected: usize,
+    /// Where each `follow` came from, most recent last.
+    back: Vec<usize>,
+    /// Scroll the selection into view on the next render. Not done every
+    /// frame, so the mouse wheel can scroll away from it.
+    reveal: bool,
+    /// `pc` when the view last followed it.
+    last_pc: Option<u16>,
+    /// Rows in view at the last render, for paging.
+    height: u16,
+    /// A breakpoint toggled by clicking its marker, for the app to set.
+    toggled: Option<u16>,
+}
+
+impl DisassemblyState {
+    /// Moves the selection `delta` items up or down.
+    pub fn select_by(&mut self, disassembly: &[Item], delta: isize) {
+        let last = disassembly.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        self.reveal = true;
+    }
+
+    /// Moves the selection a screenful up (`-1`) or down (`1`).
+    pub fn page_by(&mut self, disassembly: &[Item], pages: isize) {
+        let page = self.height.saturating_sub(1).max(1) as isize;
+        self.select_by(disassembly, pages * page);
+    }
+
+    /// Selects the item `addr` is in, remembering where it was for `back`.
+    pub fn goto(&mut self, disassembly: &[Item], addr: u16) {
+        let Some(i) = disassembly
+            .partition_point(|item| item.addr <= addr)
+            .checked_sub(1)
+        else {
+            return;
+        };
+        self.back.push(self.selected);
+        self.selected = i;
+        self.reveal = true;
+    }
+
+    /// The address of the selected instruction, if it is one.
+    pub fn selected_instr(&self, disassembly: &[Item]) -> Option<u16> {
+        let item = disassembly.get(self.selected)?;
+        item.is_code().then_some(item.addr)
+    }
+
+    pub fn take_toggled(&mut self) -> Option<u16> {
+        self.toggled.take()
+    }
+
+    /// Selects the target of the selected jump or branch.
+    pub fn follow(&mut self, disassembly: &[Item]) {
+        let target = disassembly
+            .get(self.selected)
+            .and_then(Item::target)
+            .and_then(|addr| {
+                disassembly
+                    .binary_search_by_key(&addr, |item| item.addr)
+                    .ok()
+            });
+        if let Some(target) = target {
+            self.back.push(self.selected);
+            self.selected = target;
+            self.reveal = true;
+        }
+    }
+
+    /// Goes back to where the last `follow` came from.
+    pub fn back(&mut self) {
+        if let Some(prev) = self.back.pop() {
+            self.selected = prev;
+            self.reveal = true;
+        }
+    }
+
+    /// Forgets the history, which refers to the old disassembly.
+    pub fn reset(&mut self) {
+        self.back.clear();
+        self.reveal = true;
+    }
+}
+
+/// The rows the items go on and the jumps between them, which only change
+/// with the disassembly or the symbols, so are worked out once when those
+/// arrive rather than every frame.
+#[derive(Default)]
+pub struct DisassemblyLayout {
+    /// The row of each item, after the labels before it.
+    item_rows: Vec<usize>,
+    arrows: Vec<Arrow>,
+    xrefs: HashMap<u16, Vec<u16>>,
+}
+
+impl DisassemblyLayout {
+    pub fn new(disassembly: &[Item], symbols: &Symbols) -> Self {
+        let mut item_rows = Vec::with_capacity(disassembly.len());
+        let mut row = 0;
+        for item in disassembly {
+            if symbols.label_at(item.addr).is_some() {
+                row += 1;
+            }
+            item_rows.push(row);
+            row += 1;
+        }
+        let arrows = arrows(disassembly, &item_rows);
+        let xrefs = xrefs(disassembly);
+        Self {
+            item_rows,
+            arrows,
+            xrefs,
+        }
+    }
+}
+
 pub struct DisassemblyView<'a> {
-    pub disassembly: &'a [Instr<Reg, s16>],
+    pub disassembly: &'a [Item],
+    /// `DisassemblyLayout::new` of `disassembly` and `symbols`.
+    pub layout: &'a DisassemblyLayout,
+    pub symbols: &'a Symbols,
     pub pc: u16,
+    /// While the CPU runs, the view stays put rather than chase `pc`.
+    pub running: bool,
+    /// Code breakpoints, and whether each is enabled.
+    pub breakpoints: &'a [(u16, bool)],
+    /// A click in the view, which selects the row under it, or toggles a
+    /// breakpoint if it's on the markers.
+    pub click: Option<Position>,
+}
+
+/// A line of the view: a label heading or an item.
+enum Row<'a> {
+    Label(&'a str),
+    Item(usize),
+}
+
+/// An arrow from the row of a jump to the row of its target.
+struct Arrow {
+    from: usize,
+    to: usize,
+    lane: usize,
+}
+
+impl Arrow {
+    fn rows(&self) -> (usize, usize) {
+        (self.from.min(self.to), self.from.max(self.to))
+    }
 }
 
 impl<'a> StatefulWidget for DisassemblyView<'a> {
-    type State = ScrollViewState;
+    type State = DisassemblyState;
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         if self.disassembly.is_empty() {
             return;
         }
+        state.selected = state.selected.min(self.disassembly.len() - 1);
 
-        let mut byte_idx = lark_vm::cpu::Memory::ROM_START;
-        let mut items = Vec::new();
-
-        for instr in self.disassembly.iter() {
-            let instr_txt = format!("{instr}");
-            let instr_txt = if let Some((op, args)) = instr_txt.split_once('\t') {
-                format!("{:<8}{}", op, args)
-            } else {
-                format!("{}", instr)
-            };
-
-            let item = ListItem::new(format!("0x{:04X}    {}", byte_idx, instr_txt));
+        let item_rows = &self.layout.item_rows;
+        let mut rows = Vec::with_capacity(item_rows.last().map_or(0, |&row| row + 1));
+        for (i, item) in self.disassembly.iter().enumerate() {
+            if let Some(label) = self.symbols.label_at(item.addr) {
+                rows.push(Row::Label(label));
+            }
+            rows.push(Row::Item(i));
+        }
 
-            if self.pc == byte_idx {
-                items.push(item.style(Style::new().reversed()));
-            } else {
-                items.push(item);
+        if let Some(click) = self.click.filter(|&click| area.contains(click)) {
+            let row = (click.y - area.y + state.scroll.offset().y) as usize;
+            if let Some(&Row::Item(i)) = rows.get(row) {
+                state.selected = i;
+                if click.x < area.x + MARKERS_WIDTH {
+                    state.toggled = state.selected_instr(self.disassembly);
+                }
             }
+        }
 
-            byte_idx += instr.instr_size();
+        // Follow `pc` whenever it has moved since the CPU last stopped.
+        let mut reveal = state.reveal.then_some(item_rows[state.selected]);
+        if !self.running && state.last_pc != Some(self.pc) {
+            state.last_pc = Some(self.pc);
+            let pc_item = self
+                .disassembly
+                .binary_search_by_key(&self.pc, |item| item.addr);
+            if let Ok(i) = pc_item {
+                reveal = Some(item_rows[i]);
+            }
+        }
+        state.height = area.height;
+        if let Some(row) = reveal {
+            state.reveal = false;
+            let row = row as u16;
+            let mut offset = state.scroll.offset();
+            if row < offset.y {
+                offset.y = row;
+            } else if row >= offset.y + area.height {
+                offset.y = row + 1 - area.height;
+            }
+            state.scroll.set_offset(offset);
         }
 
-        let content_height = items.len() as u16;
-        let list = List::new(items).highlight_style(Style::new().reversed());
+        let selected_row = item_rows[state.selected];
+        let gutter = Gutter::draw(&self.layout.arrows, rows.len(), selected_row);
 
+        let mut lines = Vec::with_capacity(rows.len());
+        for (row, kind) in rows.iter().enumerate() {
+            let mut spans = self.markers(kind);
+            spans.extend(gutter.spans(row));
+            match *kind {
+                Row::Label(label) => spans.push(format!("{label}:").bold().cyan()),
+                Row::Item(i) => {
+                    let item = &self.disassembly[i];
+                    let (op, args) = item.text();
+                    let text = format!(
+                        "0x{:04X}    {:<8}{}",
+                        item.addr,
+                        op,
+                        self.symbolize(item, &args)
+                    );
+                    let mut style = match item.is_code() {
+                        true => Style::new(),
+                        false => Style::new().dark_gray(),
+                    };
+                    if item.addr == self.pc {
+                        style = style.reversed();
+                    }
+                    if i == state.selected {
+                        style = style.bg(Color::DarkGray);
+                    }
+                    spans.push(Span::styled(text, style));
+                    if let Some(sources) = self.layout.xrefs.get(&item.addr) {
+                        spans.push(self.describe_xrefs(sources).dark_gray());
+                    }
+                }
+            }
+            lines.push(Line::from(spans));
+        }
+
+        let content_height = lines.len() as u16;
         let mut scroll_view = ScrollView::new(Size {
             height: content_height,
             width: area.width,
         })
         .horizontal_scrollbar_visibility(tui_scrollview::ScrollbarVisibility::Never);
 
-        scroll_view.render_widget(list, Rect::new(0, 0, area.width, content_height));
-        scroll_view.render(area, buf, state);
+        scroll_view.render_widget(
+            Paragraph::new(lines),
+            Rect::new(0, 0, area.width, content_height),
+        );
+        scroll_view.render(area, buf, &mut state.scroll);
+    }
+}
+
+impl DisassemblyView<'_> {
+    /// `*` for an enabled breakpoint (`o` for a disabled one), then `>` if
+    /// `pc` is there.
+    fn markers(&self, row: &Row) -> Vec<Span<'static>> {
+        let &Row::Item(i) = row else {
+            return vec![Span::raw(" ".repeat(MARKERS_WIDTH as usize))];
+        };
+        let addr = self.disassembly[i].addr;
+        let bp = self
+            .breakpoints
+            .iter()
+            .filter(|&&(a, _)| a == addr)
+            .map(|&(_, enabled)| enabled)
+            .max();
+        let bp = match bp {
+            Some(true) => "*".red().bold(),
+            Some(false) => "o".red(),
+            None => " ".into(),
+        };
+        let pc = match addr == self.pc {
+            true => ">".bold(),
+            false => " ".into(),
+        };
+        vec![bp, pc]
+    }
+
+    /// Replaces a jump's target with its label, if it has one.
+    fn symbolize(&self, item: &Item, args: &str) -> String {
+        let Some(label) = item.target().and_then(|addr| self.symbols.label_at(addr)) else {
+            return args.to_owned();
+        };
+        match args.rsplit_once(", ") {
+            Some((init, _)) => format!("{init}, {label}"),
+            None => label.to_owned(),
+        }
+    }
+
+    fn describe_xrefs(&self, sources: &[u16]) -> String {
+        let mut names = sources
+            .iter()
+            .take(MAX_XREFS)
+            .map(|&addr| self.symbols.describe(addr))
+            .collect::<Vec<_>>();
+        if sources.len() > MAX_XREFS {
+            names.push(format!("+{} more", sources.len() - MAX_XREFS));
+        }
+        format!("  ; from {}", names.join(", "))
+    }
+}
+
+/// An arrow for every jump to an item, each given a lane that no
+/// overlapping arrow uses. Shorter arrows get the lanes nearer the code.
+fn arrows(disassembly: &[Item], item_rows: &[usize]) -> Vec<Arrow> {
+    let mut spans = Vec::new();
+    for (i, item) in disassembly.iter().enumerate() {
+        let Some(target) = item.target() else {
+            continue;
+        };
+        if let Ok(j) = disassembly.binary_search_by_key(&target, |item| item.addr) {
+            if i != j {
+                spans.push((item_rows[i], item_rows[j]));
+            }
+        }
+    }
+    spans.sort_by_key(|&(from, to)| from.abs_diff(to));
+
+    let mut arrows: Vec<Arrow> = Vec::new();
+    for (from, to) in spans {
+        let (lo, hi) = (from.min(to), from.max(to));
+        let lane = (0..MAX_LANES).find(|&lane| {
+            arrows.iter().all(|a| {
+                let (a_lo, a_hi) = a.rows();
+                a.lane != lane || hi < a_lo || a_hi < lo
+            })
+        });
+        if let Some(lane) = lane {
+            arrows.push(Arrow { from, to, lane });
+        }
+    }
+    arrows
+}
+
+/// The addresses of the jumps to each address.
+fn xrefs(disassembly: &[Item]) -> HashMap<u16, Vec<u16>> {
+    let mut xrefs = HashMap::<u16, Vec<u16>>::new();
+    for item in disassembly {
+        if let Some(target) = item.target() {
+            xrefs.entry(target).or_default().push(item.addr);
+        }
+    }
+    xrefs
+}
+
+/// The arrows, drawn in ASCII a character per cell, with the ones touching
+/// the selected row highlighted.
+struct Gutter {
+    width: usize,
+    cells: Vec<Vec<(char, bool)>>,
+}
+
+impl Gutter {
+    fn draw(arrows: &[Arrow], rows: usize, selected_row: usize) -> Self {
+        let lanes = arrows.iter().map(|a| a.lane + 1).max().unwrap_or(0);
+        let width = match lanes {
+            0 => 0,
+            _ => lanes * 2 + 1,
+        };
+        let mut cells = vec![vec![(' ', false); width]; rows];
+
+        // Draw the highlighted arrows last so they end up on top.
+        let mut arrows = arrows.iter().collect::<Vec<_>>();
+        arrows.sort_by_key(|a| a.from == selected_row || a.to == selected_row);
+        for arrow in arrows {
+            let hl = arrow.from == selected_row || arrow.to == selected_row;
+            let x = (lanes - 1 - arrow.lane) * 2;
+            let (lo, hi) = arrow.rows();
+            for row in &mut cells[lo + 1..hi] {
+                if row[x].0 == ' ' || hl {
+                    row[x] = ('|', hl);
+                }
+            }
+            for (row, end) in [(arrow.from, '-'), (arrow.to, '>')] {
+                let cells = &mut cells[row];
+                cells[x] = ('+', hl);
+                for cell in &mut cells[x + 1..width - 1] {
+                    if cell.0 == ' ' || hl {
+                        *cell = ('-', hl);
+                    }
+                }
+                if cells[width - 1].0 != '>' || hl {
+                    cells[width - 1] = (end, hl);
+                }
+            }
+        }
+        Self { width, cells }
+    }
+
+    fn spans(&self, row: usize) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+        let cells = &self.cells[row];
+        let mut start = 0;
+        while start < self.width {
+            let hl = cells[start].1;
+            let len = cells[start..].iter().take_while(|c| c.1 == hl).count();
+            let text = cells[start..start + len]
+                .iter()
+                .map(|c| c.0)
+                .collect::<String>();
+            spans.push(match hl {
+                true => text.yellow(),
+                false => text.dark_gray(),
+            });
+            start += len;
+        }
+        if self.width > 0 {
+            spans.push(Span::raw(" "));
+        }
+        spans
     }
 }
@@ -0,0 +1,172 @@
+use ratatui::{prelude::*, widgets::*};
+
+use crate::debugger::{MemWindow, MEM_VIEW_LEN};
+
+const BYTES_PER_ROW: u16 = 16;
+
+/// A byte or word being typed in, in hex.
+pub struct Edit {
+    pub word: bool,
+    digits: String,
+}
+
+impl Edit {
+    fn max_digits(&self) -> usize {
+        match self.word {
+            true => 4,
+            false => 2,
+        }
+    }
+}
+
+/// The cursor and any edit in progress, which outlive a frame.
+#[derive(Default)]
+pub struct MemoryState {
+    /// The selected byte, or `None` to sit on the followed address (or the
+    /// start of the window) wherever the window goes.
+    cursor: Option<u16>,
+    edit: Option<Edit>,
+    /// The first row in view.
+    top: u16,
+}
+
+impl MemoryState {
+    pub fn cursor(&self, window: &MemWindow) -> u16 {
+        let follow = window.follow.as_ref().and_then(|(_, addr)| *addr);
+        self.cursor.or(follow).unwrap_or(window.base)
+    }
+
+    /// Moves the cursor `delta` bytes, cancelling any edit. Returns where to
+    /// move the window if the cursor has left it.
+    pub fn move_by(&mut self, window: &MemWindow, delta: i16) -> Option<u16> {
+        let cursor = self.cursor(window).wrapping_add_signed(delta);
+        self.cursor = Some(cursor);
+        self.edit = None;
+        match window.contains(cursor) {
+            true => None,
+            false => Some((cursor & !(BYTES_PER_ROW - 1)).saturating_sub(MEM_VIEW_LEN / 2)),
+        }
+    }
+
+    pub fn start_edit(&mut self, word: bool) {
+        self.edit = Some(Edit {
+            word,
+            digits: String::new(),
+        });
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.edit.is_some()
+    }
+
+    pub fn cancel_edit(&mut self) {
+        self.edit = None;
+    }
+
+    pub fn push_digit(&mut self, ch: char) {
+        if let Some(edit) = &mut self.edit {
+            if ch.is_ascii_hexdigit() && edit.digits.len() < edit.max_digits() {
+                edit.digits.push(ch.to_ascii_uppercase());
+            }
+        }
+    }
+
+    pub fn pop_digit(&mut self) {
+        if let Some(edit) = &mut self.edit {
+            edit.digits.pop();
+        }
+    }
+
+    /// Ends the edit, returning the address, the value typed and whether
+    /// it's a word, unless nothing was typed.
+    pub fn finish_edit(&mut self, window: &MemWindow) -> Option<(u16, u16, bool)> {
+        let edit = self.edit.take()?;
+        let value = u16::from_str_radix(&edit.digits, 16).ok()?;
+        Some((self.cursor(window), value, edit.word))
+    }
+
+    /// What the block title should say about the edit.
+    pub fn describe_edit(&self, window: &MemWindow) -> Option<String> {
+        let edit = self.edit.as_ref()?;
+        let kind = match edit.word {
+            true => "word",
+            false => "byte",
+        };
+        Some(format!(
+            "{kind} at 0x{:04X}: {:_<width$}  Enter: write  Esc: cancel",
+            self.cursor(window),
+            edit.digits,
+            width = edit.max_digits(),
+        ))
+    }
+}
+
+/// The window as rows of hex and ASCII.
+pub struct MemoryView<'a> {
+    pub window: &'a MemWindow,
+}
+
+impl<'a> StatefulWidget for MemoryView<'a> {
+    type State = MemoryState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let window = self.window;
+        let rows = window.bytes.len().div_ceil(BYTES_PER_ROW as usize) as u16;
+        let cursor = state.cursor(window);
+        let follow = window.follow.as_ref().and_then(|(_, addr)| *addr);
+        let word = state.edit.as_ref().is_some_and(|edit| edit.word);
+        let under_cursor = |addr: u16| match word {
+            true => addr.wrapping_sub(cursor) < 2,
+            false => addr == cursor,
+        };
+
+        // Keep the cursor's row in view.
+        if window.contains(cursor) {
+            let row = cursor.wrapping_sub(window.base) / BYTES_PER_ROW;
+            if row < state.top {
+                state.top = row;
+            } else if row >= state.top + area.height {
+                state.top = row + 1 - area.height;
+            }
+        }
+        state.top = state.top.min(rows.saturating_sub(area.height));
+
+        let mut lines = Vec::with_capacity(area.height as usize);
+        for row in state.top..rows.min(state.top + area.height) {
+            let start = window.base.wrapping_add(row * BYTES_PER_ROW);
+            let mut hex = vec![Span::raw(format!("0x{start:04X}: ")).dark_gray()];
+            let mut ascii = vec![Span::raw(" |")];
+            for addr in (0..BYTES_PER_ROW).map(|i| start.wrapping_add(i)) {
+                let byte = window.get(addr);
+                let mut style = match byte {
+                    Some(0) | None => Style::new().dark_gray(),
+                    Some(_) => Style::new(),
+                };
+                if window.is_changed(addr) {
+                    style = style.yellow().bold();
+                }
+                if Some(addr) == follow {
+                    style = style.cyan().underlined();
+                }
+                if under_cursor(addr) {
+                    style = style.reversed();
+                }
+                let (h, a) = match byte {
+                    Some(b) if b.is_ascii_graphic() || b == b' ' => {
+                        (format!("{b:02X}"), (b as char).to_string())
+                    }
+                    Some(b) => (format!("{b:02X}"), ".".to_owned()),
+                    None => ("--".to_owned(), " ".to_owned()),
+                };
+                hex.push(Span::styled(h, style));
+                hex.push(Span::raw(" "));
+                ascii.push(Span::styled(a, style));
+            }
+            ascii.push(Span::raw("|"));
+            hex.extend(ascii);
+            lines.push(Line::from(hex));
+        }
+
+        Paragraph::new(lines).render(area, buf);
+    }
+}
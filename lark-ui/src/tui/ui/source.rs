@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use ratatui::{prelude::*, widgets::*};
+
+/// A source file with one line highlighted, scrolled so that line is in view.
+pub struct SourceView<'a> {
+    pub path: &'a Path,
+    pub text: &'a [String],
+    /// 1-based line to highlight.
+    pub current_line: Option<usize>,
+}
+
+impl Widget for SourceView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Source: {}", self.path.display()));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let height = inner.height as usize;
+        let first = match self.current_line {
+            Some(line) => line.saturating_sub(height / 2 + 1),
+            None => 0,
+        };
+        let gutter = self.text.len().to_string().len();
+
+        let lines = self
+            .text
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(height)
+            .map(|(i, text)| {
+                let lineno = i + 1;
+                let line = Line::from(vec![
+                    Span::raw(format!("{lineno:>gutter$} ")).dark_gray(),
+                    Span::raw(text.replace('\t', "    ")),
+                ]);
+                match self.current_line == Some(lineno) {
+                    true => line.reversed(),
+                    false => line,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Paragraph::new(lines).render(inner, buf);
+    }
+}
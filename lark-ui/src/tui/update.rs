@@ -1,43 +1,36 @@
-use std::{
-    fmt::Write,
-    path::{Path, PathBuf},
-    time::Instant,
-};
+use std::time::Duration;
 
 use anyhow::Result;
-use crossterm::event::{self, Event, KeyCode, MouseButton};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers, MouseButton};
 use tui_input::backend::crossterm::EventHandler;
 
-use lark_vm::cpu::{instr::Instr, MemBlock, MemRw, Signal};
+use crate::debugger::Output;
+
+use super::{
+    ui::{DisassemblyLayout, DISASSEMBLY_TAB, MEMORY_TAB},
+    worker::{Request, Response},
+    App, RegSnapshot,
+};
 
-use super::{ui::CmdMsg, App};
+/// How long to wait for input before redrawing. The CPU runs on its own
+/// thread, so this just sets the frame rate.
+const FRAME_TIME: Duration = Duration::from_millis(16);
 
 impl App {
     // App update function
     pub fn update(&mut self) -> Result<()> {
-        if self.cpu_run_till_breakpoint {
-            self.instr_time_delta = Some(self.instr_stopwatch_start.elapsed());
-            self.instr_stopwatch_start = Instant::now();
-
-            self.cpu.step().unwrap_or_else(|e| {
-                self.cmd_err(format!("CPU Error: {:?}", e));
-            });
-        }
-
-        let ui_delay = if self.cpu_run_till_breakpoint { 0 } else { 50 };
-
-        if event::poll(std::time::Duration::from_millis(ui_delay))? {
+        if event::poll(FRAME_TIME)? {
             let e = event::read()?;
             if let Event::Mouse(m) = e {
                 match m.kind {
                     event::MouseEventKind::ScrollDown => {
                         self.cmd_output_scroll = self.cmd_output_scroll.saturating_sub(1);
-                        self.disassembly_scroll_view_state.scroll_down();
+                        self.dis_state.scroll.scroll_down();
                     }
                     event::MouseEventKind::ScrollUp => {
                         self.cmd_output_scroll =
                             (self.cmd_output_scroll + 1).min(self.cmd_output.len());
-                        self.disassembly_scroll_view_state.scroll_up();
+                        self.dis_state.scroll.scroll_up();
                     }
                     event::MouseEventKind::Down(MouseButton::Left) => {
                         self.mouse_click = Some(m);
@@ -57,10 +50,75 @@ impl App {
                         KeyCode::Home => {
                             self.cmd_input_focus = !self.cmd_input_focus;
                         }
-                        KeyCode::Esc if self.cpu_run_till_breakpoint => {
-                            self.cmd_log("CPU halted.".to_string());
-                            self.cpu_run_till_breakpoint = false;
-                            self.instr_time_delta = None;
+                        // Shift+arrows move around the Disassembly tab: up
+                        // and down select, right follows a jump, left goes
+                        // back.
+                        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right
+                            if key.modifiers.contains(KeyModifiers::SHIFT)
+                                && self.tab_idx == DISASSEMBLY_TAB =>
+                        {
+                            let dis = &self.disassembly;
+                            match key.code {
+                                KeyCode::Up => self.dis_state.select_by(dis, -1),
+                                KeyCode::Down => self.dis_state.select_by(dis, 1),
+                                KeyCode::Right => self.dis_state.follow(dis),
+                                _ => self.dis_state.back(),
+                            }
+                        }
+                        KeyCode::PageUp | KeyCode::PageDown if self.tab_idx == DISASSEMBLY_TAB => {
+                            let pages = match key.code {
+                                KeyCode::PageUp => -1,
+                                _ => 1,
+                            };
+                            self.dis_state.page_by(&self.disassembly, pages);
+                        }
+                        KeyCode::F(9) if self.tab_idx == DISASSEMBLY_TAB => {
+                            if let Some(addr) = self.dis_state.selected_instr(&self.disassembly) {
+                                self.toggle_breakpoint(addr);
+                            }
+                        }
+                        // In the Memory tab, shift+arrows move the cursor
+                        // and F2/F3 start typing a new byte or word in hex.
+                        KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right
+                            if key.modifiers.contains(KeyModifiers::SHIFT)
+                                && self.tab_idx == MEMORY_TAB =>
+                        {
+                            let delta = match key.code {
+                                KeyCode::Up => -16,
+                                KeyCode::Down => 16,
+                                KeyCode::Left => -1,
+                                _ => 1,
+                            };
+                            self.move_mem_cursor(delta);
+                        }
+                        KeyCode::PageUp | KeyCode::PageDown if self.tab_idx == MEMORY_TAB => {
+                            let delta = match key.code {
+                                KeyCode::PageUp => -0x100,
+                                _ => 0x100,
+                            };
+                            self.move_mem_cursor(delta);
+                        }
+                        KeyCode::F(n @ (2 | 3)) if self.tab_idx == MEMORY_TAB => {
+                            self.mem_state.start_edit(n == 3);
+                        }
+                        KeyCode::Char(ch) if self.editing_mem() => {
+                            self.mem_state.push_digit(ch);
+                        }
+                        KeyCode::Backspace if self.editing_mem() => {
+                            self.mem_state.pop_digit();
+                        }
+                        KeyCode::Enter if self.editing_mem() => {
+                            if let Some((addr, value, word)) =
+                                self.mem_state.finish_edit(&self.machine.memory)
+                            {
+                                self.worker.send(Request::WriteMem { addr, value, word });
+                            }
+                        }
+                        KeyCode::Esc if self.editing_mem() => {
+                            self.mem_state.cancel_edit();
+                        }
+                        KeyCode::Esc if self.machine.running => {
+                            self.worker.send(Request::Pause);
                         }
                         KeyCode::Esc => {
                             self.cmd_input.reset();
@@ -85,12 +143,7 @@ impl App {
                             self.cmd_input.handle_event(&Event::Key(key));
                         }
                         KeyCode::Char(ch) => {
-                            use lark_vm::cpu::{interrupts::Interrupt, MemRw};
-                            const KEY_CODE_ADDR: u16 = 0xF000;
-                            self.cpu.mem.write_u8(KEY_CODE_ADDR, ch as u8);
-                            self.cpu_interrupt_channel
-                                .send(Interrupt::KEY_EVENT)
-                                .expect("interrupt channel closed!");
+                            self.worker.send(Request::Key(ch as u8));
                         }
                         _ => {
                             self.cmd_input.handle_event(&Event::Key(key));
@@ -100,31 +153,89 @@ impl App {
             }
         }
 
-        // Alloc a vec so `self` isn't borrowed immutably. We want to mutate
-        // `self` in the match statement below.
-        let signals = self.cpu_signal_channel.try_iter().collect::<Vec<_>>();
+        while let Some(resp) = self.worker.try_recv() {
+            self.handle_response(resp);
+        }
 
-        for signal in signals {
-            match signal {
-                Signal::Log(msg) => {
-                    self.cmd_output.push(CmdMsg::CpuMsg(msg));
-                }
-                Signal::Halt => {
-                    self.cmd_log("CPU halted.".to_string());
-                    self.cpu_run_till_breakpoint = false;
-                }
-                Signal::Breakpoint => {
-                    self.cmd_log(format!("BREAKPOINT at pc=0x{:04x}", self.cpu.pc));
-                    self.cpu_run_till_breakpoint = false;
-                }
-                Signal::IllegalInstr => {
-                    self.cmd_err(format!("Illegal instruction at pc=0x{:04x}", self.cpu.pc));
-                    self.cpu_run_till_breakpoint = false;
+        Ok(())
+    }
+
+    pub(super) fn handle_response(&mut self, resp: Response) {
+        match resp {
+            Response::Output(output) => {
+                for out in output {
+                    match out {
+                        Output::Msg(msg) => self.cmd_output.push(msg),
+                        Output::Goto(addr) => {
+                            self.tab_idx = DISASSEMBLY_TAB;
+                            self.dis_state.goto(&self.disassembly, addr);
+                        }
+                        Output::Loaded {
+                            disassembly,
+                            files,
+                            symbols,
+                            lines,
+                        } => {
+                            self.dis_layout = DisassemblyLayout::new(&disassembly, &symbols);
+                            self.disassembly = disassembly;
+                            self.dis_state.reset();
+                            self.last_stop = None;
+                            self.prev_stop = None;
+                            self.symbols = symbols;
+                            self.sources = lines
+                                .files()
+                                .iter()
+                                .map(|path| {
+                                    let text = std::fs::read_to_string(path).unwrap_or_default();
+                                    text.lines().map(str::to_owned).collect()
+                                })
+                                .collect();
+                            self.lines = lines;
+                            self.meadowlark_src = files.meadowlark_src;
+                            self.lark_src = files.lark_src;
+                            self.romfile = files.romfile;
+                        }
+                    }
                 }
             }
+            Response::State(state) => {
+                self.machine = *state;
+                self.note_stop();
+            }
         }
+    }
 
-        Ok(())
+    /// Whenever the CPU is stopped with different registers than last time,
+    /// whether from running (or undoing) instructions, `set` or loading a
+    /// snapshot, keeps the registers from the stop before for the registers
+    /// pane to compare against.
+    fn note_stop(&mut self) {
+        if self.machine.running
+            || self
+                .last_stop
+                .as_ref()
+                .is_some_and(|last| last.matches(&self.machine))
+        {
+            return;
+        }
+        self.prev_stop = self.last_stop.replace(RegSnapshot::of(&self.machine));
+    }
+
+    /// Sets or deletes a breakpoint at `addr`, as `toggle-break` would.
+    pub(super) fn toggle_breakpoint(&mut self, addr: u16) {
+        self.worker
+            .send(Request::Cmd(format!("toggle-break 0x{addr:04X}")));
+    }
+
+    /// Whether keys go to a byte or word being typed into the Memory tab.
+    fn editing_mem(&self) -> bool {
+        self.tab_idx == MEMORY_TAB && self.mem_state.is_editing()
+    }
+
+    fn move_mem_cursor(&mut self, delta: i16) {
+        if let Some(base) = self.mem_state.move_by(&self.machine.memory, delta) {
+            self.worker.send(Request::ViewMem(base));
+        }
     }
 
     fn get_history_cmd(&self, idx: usize) -> String {
@@ -146,266 +257,28 @@ impl App {
         self.cmd_output_scroll = 0;
 
         match cmd.split_ascii_whitespace().collect::<Vec<_>>().as_slice() {
-            ["load" | "l", path] => {
-                match PathBuf::from(path)
-                    .extension()
-                    .map(|ext| ext.to_str().unwrap())
-                {
-                    Some("meadowlark" | "meadow") => self.load_meadowlark(path),
-                    Some("lark" | "asm") => self.load_asm(path),
-                    Some("bin" | "rom") => self.load_rom(Path::new(path)),
-                    _ => {
-                        self.cmd_err(format!("Unknown file extension: {}", path));
-                        self.cmd_info("  - Supported extensions: .bin, .rom".to_string());
-                    }
-                }
-            }
-            ["listing" | "program" | "prog"] => {
-                self.cmd_info("Program:".to_string());
-                let mut line = String::new();
-                let rom = self.cpu.mem.rom.mem.clone();
-                for (i, b) in rom.iter().enumerate() {
-                    line.push_str(&format!("{:02X} ", b));
-                    if i % 16 == 15 {
-                        self.cmd_info(line.clone());
-                        if line == "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 " {
-                            break;
-                        }
-                        line.clear();
-                    }
-                }
-            }
             ["clearhist"] => {
                 self.cmd_history.clear();
             }
-            // Reset the CPU and clear the virtual terminal.
-            ["reset"] => {
-                self.reset_cpu();
+            ["reg-delta", on_off @ ("on" | "off")] => {
+                self.reg_delta = *on_off == "on";
             }
-            ["registers" | "regs" | "reg"] => {
-                let mut lines = String::new();
-                write!(&mut lines, "{}", self.cpu.regs).unwrap();
-                for line in lines.lines() {
-                    self.cmd_log(line);
-                }
-            }
-            ["run"] => {
-                self.cmd_log(format!(
-                    "Running `{}`...",
-                    self.romfile
-                        .as_ref()
-                        .or(self.lark_src.as_ref())
-                        .or(self.meadowlark_src.as_ref())
-                        .map(|p| p.display().to_string())
-                        .unwrap_or("<unknown source file>".to_string())
-                ));
-                self.cpu_run_till_breakpoint = true;
+            ["quit" | "q"] => {
+                self.cmd_history.pop(); // Don't save quit command
+                self.should_quit = true;
             }
             [] | ["step" | "s"] => {
                 if cmd.is_empty() {
                     self.cmd_history.pop(); // Don't save empty step command
                 }
-                self.cmd_log("Stepping...".to_string());
-                self.cpu.step().unwrap_or_else(|e| {
-                    self.cmd_err(format!("CPU Error: {:?}", e));
-                });
-            }
-            ["hexdump" | "x"] => {
-                self.cmd_info("Hexdump of ROM:".to_string());
-                let mut line = String::new();
-                let rom = self.cpu.mem.rom.mem.clone();
-                for (i, b) in rom.iter().enumerate() {
-                    line.push_str(&format!("{b:02X} "));
-                    if i % 16 == 7 {
-                        line.push_str("   ");
-                    }
-                    if i % 16 == 15 {
-                        self.cmd_info(format!("{i:04X} | {line}"));
-                        line.clear();
-                    }
-                }
+                self.worker.send(Request::Step);
             }
-            // Examine word:
-            ["x" | "x/w", addr_str] => {
-                let Some(addr) = parse_number(addr_str) else {
-                    self.cmd_err(format!("Invalid address: `{addr_str}`"));
-                    return;
-                };
-                let word = self.cpu.mem.read_s16(addr);
-                let unsigned = word.as_u16();
-                let signed = word.as_i16();
-                self.cmd_info(format!(
-                    "(16-bit) {addr_str}: {unsigned:5}, {signed:+5}, 0x{unsigned:04X}, 0b{unsigned:016b}"
-                ));
-            }
-            // Examine byte:
-            ["x/b", addr_str] => {
-                let Some(addr) = parse_number(addr_str) else {
-                    self.cmd_err(format!("Invalid address: `{addr_str}`"));
-                    return;
-                };
-                let byte = self.cpu.mem.read_u8(addr);
-                let ch = byte as char;
-                let mut msg = format!(" (8-bit) {addr_str}: {byte:3}, 0x{byte:02X}, 0b{byte:08b}");
-                if ch.is_ascii_graphic() || ch.is_ascii_whitespace() {
-                    write!(&mut msg, ", {:?}", ch).unwrap();
-                }
-                self.cmd_info(msg);
-            }
-            ["hexdump" | "x", lo, "..", hi] => {
-                self.cmd_info("Hexdump of ROM:".to_string());
-                let mut line = String::new();
-                let Some(lo) = parse_number(lo) else {
-                    self.cmd_err(format!("Invalid low address: `{lo}`"));
-                    return;
-                };
-                let Some(hi) = parse_number(hi) else {
-                    self.cmd_err(format!("Invalid high address: `{hi}`"));
-                    return;
-                };
-                for i in lo..(hi - lo) {
-                    let b = self.cpu.mem.read_u8(i);
-                    line.push_str(&format!("{b:02X} "));
-                    if i % 16 == 7 {
-                        line.push_str("   ");
-                    }
-                    if i % 16 == 15 {
-                        self.cmd_info(format!("{i:04X} | {line}"));
-                        line.clear();
-                    }
-                }
-                if !line.is_empty() {
-                    self.cmd_info(line);
-                }
-            }
-            ["hexdump" | "x", base, ":+", len] => {
-                self.cmd_info("Hexdump of ROM:".to_string());
-                let mut line = String::new();
-                let Some(base) = parse_number(base) else {
-                    self.cmd_err(format!("Invalid base address: `{base}`"));
-                    return;
-                };
-                let Some(len) = parse_number(len) else {
-                    self.cmd_err(format!("Invalid length: `{len}`"));
-                    return;
-                };
-                for i in base..(base + len) {
-                    let b = self.cpu.mem.read_u8(i);
-                    line.push_str(&format!("{b:02X} "));
-                    if i % 16 == 7 {
-                        line.push_str("   ");
-                    }
-                    if i % 16 == 15 {
-                        self.cmd_info(format!("{i:04X} | {line}"));
-                        line.clear();
-                    }
-                }
-                if !line.is_empty() {
-                    self.cmd_info(line);
-                }
-            }
-            ["help" | "h" | "?"] => {
-                self.cmd_info("Commands:".to_string());
-                self.cmd_info("  - load <PATH> (l)".to_string());
-                self.cmd_info("  - step (s, ENTER)".to_string());
-                self.cmd_info("  - run".to_string());
-                self.cmd_info("  - reset".to_string());
-                self.cmd_info("  - registers (regs)".to_string());
-                self.cmd_info("  - program (prog, listing)".to_string());
-                self.cmd_info("  - hexdump (x)".to_string());
-                self.cmd_info("  - hexdump (x) <LOW> .. <HIGH>".to_string());
-                self.cmd_info("  - hexdump (x) <BASE> :+ <LEN>".to_string());
-                self.cmd_info("  - clearhist".to_string());
-                self.cmd_info("  - help (h, ?)".to_string());
-                self.cmd_info("  - quit (q)".to_string());
-            }
-            ["quit" | "q"] => {
-                self.cmd_history.pop(); // Don't save quit command
-                self.should_quit = true;
+            ["run"] => {
+                self.worker.send(Request::Run);
             }
             _ => {
-                self.cmd_err(format!("Unknown command: `{}`", cmd));
+                self.worker.send(Request::Cmd(cmd.to_owned()));
             }
         }
     }
-
-    pub(crate) fn load_meadowlark(&mut self, path: &str) {
-        let path = PathBuf::from(path);
-        match meadowlark::compile(&path, false) {
-            Ok(bin_path) => {
-                self.load_rom(bin_path.as_path());
-                self.meadowlark_src = Some(path);
-            }
-            Err(e) => {
-                self.cmd_err(format!("Error compiling Meadowlark file: {}", e));
-            }
-        }
-    }
-
-    pub(crate) fn load_asm(&mut self, _path: &str) {
-        todo!()
-    }
-
-    pub(crate) fn load_rom(&mut self, path: &Path) {
-        let rom = match std::fs::read(path) {
-            Ok(rom) => rom,
-            Err(e) => {
-                self.cmd_err(format!("Error reading ROM file: {}", e));
-                return;
-            }
-        };
-
-        let romfile_size = rom.len();
-
-        let rom = match MemBlock::from_vec(rom) {
-            Some(rom) => rom,
-            None => {
-                self.cmd_err(format!("ROM file too large: {}", path.display()));
-                self.cmd_info(format!("  - Max ROM size: {}", lark_vm::cpu::ROM_SIZE));
-                self.cmd_info(format!("  - Given ROM size: {romfile_size}"));
-                return;
-            }
-        };
-
-        self.reset_cpu();
-        self.romfile = Some(path.to_path_buf());
-        self.cpu.load_rom(rom);
-        self.disassembly = self.disassembly();
-
-        self.cmd_info(format!(
-            "Loaded ROM file `{}` ({romfile_size} bytes)",
-            self.romfile.as_ref().unwrap().display()
-        ));
-    }
-
-    fn clear_vtty(&mut self) {
-        let mut vtty_buf = self.vtty_buf.borrow_mut();
-        vtty_buf.mem.fill(0);
-    }
-
-    fn reset_cpu(&mut self) {
-        self.cpu.reset();
-        self.clear_vtty();
-    }
-
-    fn disassembly(&mut self) -> Vec<Instr> {
-        let machine_code: &[u8] = self.cpu.mem.rom.as_ref();
-        let mut instrs = Vec::new();
-        if let Err(e) = Instr::disassemble(&mut instrs, machine_code) {
-            self.cmd_err(format!("Disassembly error: {:?}", e));
-        }
-        instrs
-    }
-}
-
-fn parse_number(s: &str) -> Option<u16> {
-    if let Some(stripped) = s.strip_prefix("0b") {
-        u16::from_str_radix(stripped, 2).ok()
-    } else if let Some(stripped) = s.strip_prefix("0o") {
-        return u16::from_str_radix(stripped, 8).ok();
-    } else if let Some(stripped) = s.strip_prefix("0x") {
-        return u16::from_str_radix(stripped, 16).ok();
-    } else {
-        return s.parse().ok();
-    }
 }
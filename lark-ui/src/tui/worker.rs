@@ -0,0 +1,165 @@
+//! The thread the CPU runs on. The UI talks to it with `Request`s and gets
+//! back `Response`s, so a long run never holds up drawing or Esc.
+//!
+//! `Cpu` shares the VTTY through an `Rc`, so the `Debugger` has to be built on
+//! the worker thread itself; only the `SourceFiles` to load cross over.
+
+use std::{
+    sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::debugger::{Debugger, MachineState, Output, SourceFiles, WATCH_INTERVAL};
+
+/// How often to send the machine state during a run. Nothing draws faster
+/// than this, and a paced run might otherwise send one per instruction.
+const STATE_INTERVAL: Duration = Duration::from_millis(16);
+
+pub enum Request {
+    /// A console command for the debugger.
+    Cmd(String),
+    Step,
+    Run,
+    /// Stop a run, as if it hit a breakpoint.
+    Pause,
+    /// A key press for the program running on the CPU.
+    Key(u8),
+    /// Move the Memory tab's window.
+    ViewMem(u16),
+    /// Store a byte or word, from the Memory tab's editor.
+    WriteMem {
+        addr: u16,
+        value: u16,
+        word: bool,
+    },
+}
+
+pub enum Response {
+    Output(Vec<Output>),
+    /// Sent once at startup and after every batch of requests or run time.
+    State(Box<MachineState>),
+}
+
+pub struct Worker {
+    requests: Sender<Request>,
+    responses: Receiver<Response>,
+}
+
+impl Worker {
+    pub fn spawn(files: SourceFiles, clock_hz: Option<u32>) -> Self {
+        let (req_tx, req_rx) = mpsc::channel();
+        let (resp_tx, resp_rx) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("lark-cpu".to_string())
+            .spawn(move || {
+                let mut dbg = Debugger::new(files);
+                dbg.set_clock_hz(clock_hz);
+                serve(dbg, req_rx, resp_tx)
+            })
+            .expect("failed to spawn CPU thread");
+
+        Self {
+            requests: req_tx,
+            responses: resp_rx,
+        }
+    }
+
+    pub fn send(&self, req: Request) {
+        self.requests.send(req).expect("CPU thread exited!");
+    }
+
+    pub fn try_recv(&self) -> Option<Response> {
+        match self.responses.try_recv() {
+            Ok(resp) => Some(resp),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => panic!("CPU thread exited!"),
+        }
+    }
+
+    pub fn recv(&self) -> Response {
+        self.responses.recv().expect("CPU thread exited!")
+    }
+}
+
+/// Handles requests until the UI hangs up. While the CPU is running, requests
+/// are only checked between batches, otherwise the thread sleeps until one
+/// arrives (or, in a paced run, until the next instruction is due, or with
+/// `watch-src` on, until it's time to check the source files again).
+fn serve(mut dbg: Debugger, requests: Receiver<Request>, responses: Sender<Response>) {
+    let mut last_state = Instant::now();
+    if publish(&mut dbg, &responses, true).is_err() {
+        return;
+    }
+
+    loop {
+        if !dbg.is_running() && dbg.watching_src() {
+            match requests.recv_timeout(WATCH_INTERVAL) {
+                Ok(req) => handle(&mut dbg, req),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        } else if !dbg.is_running() {
+            match requests.recv() {
+                Ok(req) => handle(&mut dbg, req),
+                Err(RecvError) => return,
+            }
+        } else if let Some(wait) = dbg.time_until_next_instr() {
+            match requests.recv_timeout(wait) {
+                Ok(req) => handle(&mut dbg, req),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        loop {
+            match requests.try_recv() {
+                Ok(req) => handle(&mut dbg, req),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+
+        dbg.poll_src();
+        if dbg.is_running() {
+            dbg.run_batch();
+        }
+        dbg.handle_signals();
+
+        let send_state = !dbg.is_running() || last_state.elapsed() >= STATE_INTERVAL;
+        if send_state {
+            last_state = Instant::now();
+        }
+        if publish(&mut dbg, &responses, send_state).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle(dbg: &mut Debugger, req: Request) {
+    match req {
+        Request::Cmd(cmd) => dbg.do_cmd(&cmd),
+        Request::Step => dbg.step(),
+        Request::Run => dbg.run(),
+        Request::Pause => dbg.pause(),
+        Request::Key(ch) => dbg.key_event(ch),
+        Request::ViewMem(base) => dbg.view_mem(base),
+        Request::WriteMem { addr, value, word } => dbg.write_mem(addr, value, word),
+    }
+}
+
+fn publish(
+    dbg: &mut Debugger,
+    responses: &Sender<Response>,
+    send_state: bool,
+) -> Result<(), mpsc::SendError<Response>> {
+    let output = dbg.take_output();
+    if !output.is_empty() {
+        responses.send(Response::Output(output))?;
+    }
+    if send_state {
+        responses.send(Response::State(Box::new(dbg.state())))?;
+    }
+    Ok(())
+}
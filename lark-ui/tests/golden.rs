@@ -0,0 +1,29 @@
+//! Runs every `.larktest` file in `tests/golden`.
+
+use std::path::Path;
+
+use lark_ui::golden::GoldenTest;
+
+#[test]
+fn golden() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    let mut tests = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "larktest"))
+        .collect::<Vec<_>>();
+    tests.sort();
+    assert!(!tests.is_empty(), "no tests in `{}`", dir.display());
+
+    let failures = tests
+        .iter()
+        .filter_map(|path| {
+            let report = GoldenTest::load(path)
+                .and_then(|test| test.run(Some(&out_dir)))
+                .err()?;
+            Some(format!("{}:\n{report}", path.display()))
+        })
+        .collect::<Vec<_>>();
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}